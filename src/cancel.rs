@@ -0,0 +1,205 @@
+//! Per-request timeouts, and cancelling requests that are still running.
+
+use anyhow::{Context, Result};
+use futures_util::future::{self, Either};
+use postgres_native_tls::MakeTlsConnector;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio_postgres::{CancelToken, NoTls};
+
+/// How often a cancelled request's statements are cancelled again until it stops,
+/// so a request running several statements can't outlive its cancellation.
+const CANCEL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `timeout_ms`, `lock_timeout_ms` and `request_id` of a request.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Cancel the request once it has run this long.
+    pub timeout: Option<Duration>,
+    /// How long each statement may wait for a lock, set with `SET LOCAL`.
+    pub lock_timeout: Option<Duration>,
+    /// Name the request so `postgres.cancel` can find it.
+    pub request_id: Option<String>,
+}
+
+impl Limits {
+    /// `SET LOCAL` for the lock timeout, to run at the start of a transaction.
+    pub fn set_local(&self) -> Option<String> {
+        self.lock_timeout
+            .map(|timeout| format!("SET LOCAL lock_timeout = {}", timeout.as_millis()))
+    }
+}
+
+/// Why a request was cancelled; error context on what the cancellation caused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cancelled {
+    Timeout(Duration),
+    /// By `postgres.cancel`.
+    Request,
+}
+
+impl Cancelled {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Cancelled::Timeout(_) => "timeout",
+            Cancelled::Request => "request",
+        }
+    }
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cancelled::Timeout(timeout) => {
+                write!(f, "Cancelled after timeout_ms ({} ms)", timeout.as_millis())
+            }
+            Cancelled::Request => f.write_str("Cancelled by postgres.cancel"),
+        }
+    }
+}
+
+/// A request in progress.
+pub struct Running {
+    id: Option<String>,
+    timeout: Option<Duration>,
+    /// Cancels whatever the request's current connection is running, with the TLS
+    /// connector it was opened with.
+    token: Mutex<Option<(CancelToken, Option<MakeTlsConnector>)>>,
+    cancel: Notify,
+    cancelled: Mutex<Option<Cancelled>>,
+}
+
+impl Running {
+    /// Record the connection the request runs on, and the TLS connector it was opened
+    /// with, so its statements can be cancelled.
+    ///
+    /// Fails once the request has been cancelled, e.g. while it waited for the connection.
+    pub fn attach(
+        &self,
+        client: &tokio_postgres::Client,
+        tls: Option<&MakeTlsConnector>,
+    ) -> Result<()> {
+        if self.cancelled.lock().unwrap().is_some() {
+            anyhow::bail!("Request was cancelled before it reached the database");
+        }
+        *self.token.lock().unwrap() = Some((client.cancel_token(), tls.cloned()));
+        Ok(())
+    }
+
+    /// Run `work`, cancelling its statements when the timeout passes or
+    /// `postgres.cancel` asks.
+    ///
+    /// `work` is never dropped halfway: it runs until the server's cancellation
+    /// error reaches it, so its connection is left in a known state.
+    pub async fn run<T>(&self, work: impl Future<Output = Result<T>>) -> Result<T> {
+        let mut work = pin!(work);
+        let deadline = pin!(async {
+            match self.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => future::pending().await,
+            }
+        });
+        let cancel = pin!(self.cancel.notified());
+        let trigger = future::select(deadline, cancel);
+        let reason = match future::select(work.as_mut(), trigger).await {
+            Either::Left((result, _)) => return result,
+            Either::Right((Either::Left(_), _)) => {
+                Cancelled::Timeout(self.timeout.unwrap_or_default())
+            }
+            Either::Right((Either::Right(_), _)) => Cancelled::Request,
+        };
+        *self.cancelled.lock().unwrap() = Some(reason);
+
+        loop {
+            let token = self.token.lock().unwrap().clone();
+            if let Some((token, tls)) = token {
+                let cancelled = match tls {
+                    Some(tls) => token.cancel_query(tls).await,
+                    None => token.cancel_query(NoTls).await,
+                };
+                if let Err(e) = cancelled {
+                    tracing::warn!("Failed to cancel request: {}", e);
+                }
+            }
+            if let Ok(result) = tokio::time::timeout(CANCEL_INTERVAL, work.as_mut()).await {
+                return result.context(reason);
+            }
+        }
+    }
+}
+
+/// Requests in progress that have a `request_id`.
+#[derive(Default)]
+pub struct InFlight {
+    running: Mutex<HashMap<String, Arc<Running>>>,
+}
+
+impl InFlight {
+    /// Start a request under `limits`, registering it if it has a `request_id`.
+    pub fn start(&self, limits: &Limits) -> Result<Started<'_>> {
+        let running = Arc::new(Running {
+            id: limits.request_id.clone(),
+            timeout: limits.timeout,
+            token: Mutex::new(None),
+            cancel: Notify::new(),
+            cancelled: Mutex::new(None),
+        });
+        if let Some(id) = &running.id {
+            let mut map = self.running.lock().unwrap();
+            if map.contains_key(id) {
+                anyhow::bail!("A request with request_id {} is already running", id);
+            }
+            map.insert(id.clone(), running.clone());
+        }
+        Ok(Started {
+            in_flight: self,
+            running,
+        })
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.running.lock().unwrap().contains_key(id)
+    }
+
+    /// Cancel the running request named `id`.
+    pub fn cancel(&self, id: &str) -> Result<()> {
+        let running = self
+            .running
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No running request with request_id {}", id))?;
+        // Stored if the request isn't waiting yet, so the cancellation isn't lost
+        running.cancel.notify_one();
+        Ok(())
+    }
+}
+
+/// A started request; it is unregistered when dropped.
+pub struct Started<'a> {
+    in_flight: &'a InFlight,
+    running: Arc<Running>,
+}
+
+impl Deref for Started<'_> {
+    type Target = Running;
+
+    fn deref(&self) -> &Running {
+        &self.running
+    }
+}
+
+impl Drop for Started<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = &self.running.id {
+            self.in_flight.running.lock().unwrap().remove(id);
+        }
+    }
+}
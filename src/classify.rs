@@ -0,0 +1,836 @@
+//! Classify SQL by what it can do, before it is sent to the server.
+//!
+//! This looks at keywords only: a `SELECT` calling a function that writes is
+//! still a read. Read-only connections are backed by the server's
+//! `default_transaction_read_only` for that reason.
+
+use serde::{Deserialize, Serialize};
+
+/// What a statement can do, from least to most privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementKind {
+    /// SELECT, VALUES, TABLE, SHOW, plain EXPLAIN.
+    Read,
+    /// INSERT, UPDATE, DELETE, MERGE, TRUNCATE, COPY FROM, CALL, DO, ...
+    Write,
+    /// CREATE, ALTER, DROP, COMMENT, GRANT, REVOKE, SELECT INTO, ...
+    Ddl,
+    /// Session, transaction and maintenance commands, and anything unrecognised.
+    Admin,
+}
+
+impl StatementKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatementKind::Read => "read",
+            StatementKind::Write => "write",
+            StatementKind::Ddl => "ddl",
+            StatementKind::Admin => "admin",
+        }
+    }
+}
+
+/// The kind of a statement and the command that decided it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Classification {
+    pub kind: StatementKind,
+    /// Leading keyword(s), e.g. `DELETE` or `ALTER SYSTEM`.
+    pub command: String,
+}
+
+/// Statements that destroy data wholesale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DestructiveKind {
+    /// UPDATE without a WHERE clause.
+    UnboundedUpdate,
+    /// DELETE without a WHERE clause.
+    UnboundedDelete,
+    Truncate,
+    /// DROP of any object.
+    Drop,
+    /// ALTER TABLE ... DROP [COLUMN].
+    DropColumn,
+}
+
+impl DestructiveKind {
+    pub const ALL: [DestructiveKind; 5] = [
+        DestructiveKind::UnboundedUpdate,
+        DestructiveKind::UnboundedDelete,
+        DestructiveKind::Truncate,
+        DestructiveKind::Drop,
+        DestructiveKind::DropColumn,
+    ];
+}
+
+/// A destructive statement and the tables it empties, drops or rewrites.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destructive {
+    pub kind: DestructiveKind,
+    /// Table names as written, possibly schema-qualified and quoted. Empty for
+    /// drops of anything but tables.
+    pub tables: Vec<String>,
+}
+
+/// Classify `sql`; with several statements, the most privileged one wins.
+pub fn classify(sql: &str) -> Classification {
+    let tokens = tokenize(sql);
+    tokens
+        .split(|token| *token == Token::Semicolon)
+        .map(classify_statement)
+        .max_by_key(|c| c.kind)
+        .unwrap_or(Classification {
+            kind: StatementKind::Read,
+            command: String::new(),
+        })
+}
+
+/// The first statement in `sql` that starts, ends or reshapes the transaction it
+/// runs in: BEGIN, START TRANSACTION, COMMIT, END, ROLLBACK, ABORT, PREPARE
+/// TRANSACTION, COMMIT/ROLLBACK PREPARED, SAVEPOINT, RELEASE or SET TRANSACTION.
+pub fn transaction_control(sql: &str) -> Option<Classification> {
+    tokenize(sql)
+        .split(|token| *token == Token::Semicolon)
+        .find_map(|tokens| {
+            let word = |i: usize| match tokens.get(i) {
+                Some(Token::Word(w)) => w.as_str(),
+                _ => "",
+            };
+            let command = match (word(0), word(1)) {
+                (first @ ("COMMIT" | "ROLLBACK"), "PREPARED") => format!("{} PREPARED", first),
+                (first @ ("START" | "PREPARE" | "SET"), "TRANSACTION") => {
+                    format!("{} TRANSACTION", first)
+                }
+                (
+                    first @ ("BEGIN" | "COMMIT" | "END" | "ROLLBACK" | "ABORT" | "SAVEPOINT"
+                    | "RELEASE"),
+                    _,
+                ) => first.to_string(),
+                _ => return None,
+            };
+            Some(Classification {
+                kind: StatementKind::Admin,
+                command,
+            })
+        })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// An unquoted word, uppercased.
+    Word(String),
+    /// A quoted identifier, quotes included.
+    Quoted(String),
+    Open,
+    Close,
+    Semicolon,
+    Dot,
+    Comma,
+    /// Literals, operators and parameters.
+    Other,
+}
+
+/// Split SQL into tokens, skipping comments and the contents of literals.
+fn tokenize(sql: &str) -> Vec<Token> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            _ if c.is_whitespace() => i += 1,
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                // Block comments nest
+                let mut depth = 0;
+                while i < chars.len() {
+                    if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                        depth += 1;
+                        i += 2;
+                    } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            '\'' => {
+                i = skip_quoted(&chars, i, '\'', false);
+                tokens.push(Token::Other);
+            }
+            '"' => {
+                let start = i;
+                i = skip_quoted(&chars, i, '"', false);
+                tokens.push(Token::Quoted(chars[start..i].iter().collect()));
+            }
+            '$' => match dollar_tag(&chars, i) {
+                Some(tag) => {
+                    let body = i + tag.len();
+                    i = find(&chars, body, &tag).map_or(chars.len(), |end| end + tag.len());
+                    tokens.push(Token::Other);
+                }
+                None => {
+                    i += 1;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                    tokens.push(Token::Other);
+                }
+            },
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            ';' => {
+                tokens.push(Token::Semicolon);
+                i += 1;
+            }
+            '.' if !chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                tokens.push(Token::Dot);
+                i += 1;
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                i += 1;
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                if chars.get(i) == Some(&'\'') {
+                    // Prefixed literal: E'...' honours backslash escapes
+                    i = skip_quoted(&chars, i, '\'', word.eq_ignore_ascii_case("e"));
+                    tokens.push(Token::Other);
+                } else {
+                    tokens.push(Token::Word(word.to_uppercase()));
+                }
+            }
+            _ => {
+                tokens.push(Token::Other);
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+/// Index just past the quoted text opening at `start`; doubled quotes are escapes.
+fn skip_quoted(chars: &[char], start: usize, quote: char, backslashes: bool) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        if backslashes && chars[i] == '\\' {
+            i += 2;
+        } else if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                i += 2;
+            } else {
+                return i + 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    chars.len()
+}
+
+/// The `$tag$` opening a dollar-quoted string at `start`, if there is one.
+fn dollar_tag(chars: &[char], start: usize) -> Option<Vec<char>> {
+    let mut i = start + 1;
+    if chars.get(i).is_some_and(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    while let Some(&c) = chars.get(i) {
+        if c == '$' {
+            return Some(chars[start..=i].to_vec());
+        }
+        if !(c.is_alphanumeric() || c == '_') {
+            return None;
+        }
+        i += 1;
+    }
+    None
+}
+
+fn find(chars: &[char], from: usize, needle: &[char]) -> Option<usize> {
+    (from..=chars.len().saturating_sub(needle.len())).find(|&i| chars[i..].starts_with(needle))
+}
+
+fn classify_statement(tokens: &[Token]) -> Classification {
+    // A statement may open with parentheses, e.g. `(SELECT ...) UNION (SELECT ...)`
+    let start = tokens
+        .iter()
+        .position(|t| *t != Token::Open)
+        .unwrap_or(tokens.len());
+    let tokens = &tokens[start..];
+    let Some(Token::Word(first)) = tokens.first() else {
+        return Classification {
+            kind: if tokens.is_empty() {
+                StatementKind::Read
+            } else {
+                StatementKind::Admin
+            },
+            command: String::new(),
+        };
+    };
+    let second = match tokens.get(1) {
+        Some(Token::Word(word)) => word.as_str(),
+        _ => "",
+    };
+    let command = first.clone();
+
+    let kind = match first.as_str() {
+        "SELECT" if has_top_level_word(tokens, "INTO") => {
+            return Classification {
+                kind: StatementKind::Ddl,
+                command: "SELECT INTO".to_string(),
+            }
+        }
+        "SELECT" | "VALUES" | "TABLE" | "SHOW" => StatementKind::Read,
+        "WITH" => return classify_with(tokens),
+        "EXPLAIN" => return classify_explain(tokens),
+        "COPY" if has_top_level_word(tokens, "FROM") => StatementKind::Write,
+        "COPY" if has_top_level_word(tokens, "STDOUT") => StatementKind::Read,
+        // COPY TO a server file or program
+        "COPY" => StatementKind::Admin,
+        "INSERT" | "UPDATE" | "DELETE" | "MERGE" | "TRUNCATE" | "CALL" | "DO" | "REFRESH" => {
+            StatementKind::Write
+        }
+        "ALTER" if second == "SYSTEM" => {
+            return Classification {
+                kind: StatementKind::Admin,
+                command: "ALTER SYSTEM".to_string(),
+            }
+        }
+        "CREATE" | "ALTER" | "DROP" | "COMMENT" | "GRANT" | "REVOKE" | "SECURITY" | "IMPORT"
+        | "REASSIGN" => StatementKind::Ddl,
+        _ => StatementKind::Admin,
+    };
+    Classification { kind, command }
+}
+
+/// Whether `word` appears outside any parentheses.
+fn has_top_level_word(tokens: &[Token], word: &str) -> bool {
+    let mut depth = 0i32;
+    tokens.iter().any(|t| match t {
+        Token::Open => {
+            depth += 1;
+            false
+        }
+        Token::Close => {
+            depth -= 1;
+            false
+        }
+        Token::Word(w) => depth == 0 && w == word,
+        _ => false,
+    })
+}
+
+/// `WITH` is as privileged as its main statement or any data-modifying CTE in it.
+///
+/// The command is `WITH` plus the verb that decided the kind.
+fn classify_with(tokens: &[Token]) -> Classification {
+    let modifying_cte = tokens.windows(2).find_map(|pair| match &pair[1] {
+        Token::Word(w)
+            if pair[0] == Token::Open
+                && matches!(w.as_str(), "INSERT" | "UPDATE" | "DELETE" | "MERGE") =>
+        {
+            Some(w.clone())
+        }
+        _ => None,
+    });
+
+    let mut classification = match with_main_statement(tokens) {
+        Some(i) => classify_statement(&tokens[i..]),
+        None => Classification {
+            kind: StatementKind::Admin,
+            command: String::new(),
+        },
+    };
+    if let Some(verb) = modifying_cte {
+        if classification.kind < StatementKind::Write {
+            classification.kind = StatementKind::Write;
+            classification.command = verb;
+        }
+    }
+    classification.command = format!("WITH {}", classification.command)
+        .trim_end()
+        .to_string();
+    classification
+}
+
+/// Where the main statement of a `WITH` query starts, after its CTEs.
+fn with_main_statement(tokens: &[Token]) -> Option<usize> {
+    const MAIN: [&str; 7] = [
+        "SELECT", "VALUES", "TABLE", "INSERT", "UPDATE", "DELETE", "MERGE",
+    ];
+
+    let mut depth = 0i32;
+    tokens
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find_map(|(i, t)| match t {
+            Token::Open => {
+                depth += 1;
+                None
+            }
+            Token::Close => {
+                depth -= 1;
+                None
+            }
+            Token::Word(w) if depth == 0 && MAIN.contains(&w.as_str()) => Some(i),
+            _ => None,
+        })
+}
+
+/// `EXPLAIN` only runs its statement with `ANALYZE`.
+fn classify_explain(tokens: &[Token]) -> Classification {
+    match explain_analyze_target(tokens) {
+        None => Classification {
+            kind: StatementKind::Read,
+            command: "EXPLAIN".to_string(),
+        },
+        Some(i) => {
+            let explained = classify_statement(&tokens[i..]);
+            Classification {
+                kind: explained.kind,
+                command: format!("EXPLAIN ANALYZE {}", explained.command)
+                    .trim_end()
+                    .to_string(),
+            }
+        }
+    }
+}
+
+/// Where the statement run by `EXPLAIN ANALYZE` starts; `None` without `ANALYZE`.
+fn explain_analyze_target(tokens: &[Token]) -> Option<usize> {
+    let mut analyze = false;
+    let mut i = 1;
+    if tokens.get(i) == Some(&Token::Open) {
+        // EXPLAIN (ANALYZE [boolean], ...) statement
+        let mut depth = 0;
+        while let Some(token) = tokens.get(i) {
+            match token {
+                Token::Open => depth += 1,
+                Token::Close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        i += 1;
+                        break;
+                    }
+                }
+                Token::Word(w) if w == "ANALYZE" || w == "ANALYSE" => {
+                    analyze = !matches!(
+                        tokens.get(i + 1),
+                        Some(Token::Word(v)) if v == "FALSE" || v == "OFF"
+                    );
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    } else {
+        while let Some(Token::Word(w)) = tokens.get(i) {
+            match w.as_str() {
+                "ANALYZE" | "ANALYSE" => analyze = true,
+                "VERBOSE" => {}
+                _ => break,
+            }
+            i += 1;
+        }
+    }
+
+    analyze.then_some(i.min(tokens.len()))
+}
+
+/// Find destructive statements in `sql`, including data-modifying CTEs and
+/// statements run by `EXPLAIN ANALYZE`.
+pub fn destructive(sql: &str) -> Vec<Destructive> {
+    let mut found = Vec::new();
+    for statement in tokenize(sql).split(|token| *token == Token::Semicolon) {
+        find_destructive(statement, &mut found);
+    }
+    found
+}
+
+fn find_destructive(tokens: &[Token], found: &mut Vec<Destructive>) {
+    let start = tokens
+        .iter()
+        .position(|t| *t != Token::Open)
+        .unwrap_or(tokens.len());
+    let tokens = &tokens[start..];
+    let word = |i: usize| match tokens.get(i) {
+        Some(Token::Word(w)) => w.as_str(),
+        _ => "",
+    };
+
+    match word(0) {
+        "WITH" => {
+            for (i, pair) in tokens.windows(2).enumerate() {
+                if pair[0] == Token::Open && matches!(word(i + 1), "UPDATE" | "DELETE") {
+                    find_destructive(&tokens[i + 1..closing_paren(tokens, i)], found);
+                }
+            }
+            if let Some(main) = with_main_statement(tokens) {
+                find_destructive(&tokens[main..], found);
+            }
+        }
+        "EXPLAIN" => {
+            if let Some(i) = explain_analyze_target(tokens) {
+                find_destructive(&tokens[i..], found);
+            }
+        }
+        "UPDATE" if !has_top_level_word(tokens, "WHERE") => found.push(Destructive {
+            kind: DestructiveKind::UnboundedUpdate,
+            tables: table_names(tokens, 1, false),
+        }),
+        "DELETE" if !has_top_level_word(tokens, "WHERE") => found.push(Destructive {
+            kind: DestructiveKind::UnboundedDelete,
+            tables: table_names(tokens, skip_words(tokens, 1, &["FROM"]), false),
+        }),
+        "TRUNCATE" => found.push(Destructive {
+            kind: DestructiveKind::Truncate,
+            tables: table_names(tokens, skip_words(tokens, 1, &["TABLE"]), true),
+        }),
+        "DROP" => found.push(Destructive {
+            kind: DestructiveKind::Drop,
+            tables: if word(1) == "TABLE" {
+                table_names(tokens, skip_words(tokens, 2, &["IF", "EXISTS"]), true)
+            } else {
+                Vec::new()
+            },
+        }),
+        "ALTER" if word(1) == "TABLE" && drops_column(tokens) => found.push(Destructive {
+            kind: DestructiveKind::DropColumn,
+            tables: table_names(tokens, skip_words(tokens, 2, &["IF", "EXISTS"]), false),
+        }),
+        _ => {}
+    }
+}
+
+/// Index of the `)` closing the `(` at `open`, or the end.
+fn closing_paren(tokens: &[Token], open: usize) -> usize {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token {
+            Token::Open => depth += 1,
+            Token::Close => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+/// `i` moved past `words` if they follow it, otherwise `i`.
+fn skip_words(tokens: &[Token], i: usize, words: &[&str]) -> usize {
+    let present = words
+        .iter()
+        .enumerate()
+        .all(|(n, word)| matches!(tokens.get(i + n), Some(Token::Word(w)) if w == word));
+    if present {
+        i + words.len()
+    } else {
+        i
+    }
+}
+
+/// The table name starting at `i`, or a comma-separated list of them with `list`.
+fn table_names(tokens: &[Token], mut i: usize, list: bool) -> Vec<String> {
+    let mut names = Vec::new();
+    loop {
+        i = skip_words(tokens, i, &["ONLY"]);
+        let mut parts = Vec::new();
+        loop {
+            match tokens.get(i) {
+                // Unquoted identifiers fold to lower case
+                Some(Token::Word(w)) => parts.push(w.to_lowercase()),
+                Some(Token::Quoted(q)) => parts.push(q.clone()),
+                _ => break,
+            }
+            i += 1;
+            if tokens.get(i) != Some(&Token::Dot) {
+                break;
+            }
+            i += 1;
+        }
+        if parts.is_empty() {
+            break;
+        }
+        names.push(parts.join("."));
+        // `name *` includes descendant tables
+        if tokens.get(i) == Some(&Token::Other) {
+            i += 1;
+        }
+        if !list || tokens.get(i) != Some(&Token::Comma) {
+            break;
+        }
+        i += 1;
+    }
+    names
+}
+
+/// Whether an `ALTER TABLE` drops a column, as opposed to a constraint, a
+/// default, NOT NULL, an identity or a generation expression.
+fn drops_column(tokens: &[Token]) -> bool {
+    const NOT_COLUMNS: [&str; 5] = ["CONSTRAINT", "DEFAULT", "NOT", "IDENTITY", "EXPRESSION"];
+
+    let mut depth = 0i32;
+    tokens.iter().enumerate().any(|(i, t)| match t {
+        Token::Open => {
+            depth += 1;
+            false
+        }
+        Token::Close => {
+            depth -= 1;
+            false
+        }
+        Token::Word(w) if depth == 0 && w == "DROP" => !matches!(
+            tokens.get(i + 1),
+            Some(Token::Word(next)) if NOT_COLUMNS.contains(&next.as_str())
+        ),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(sql: &str) -> StatementKind {
+        classify(sql).kind
+    }
+
+    fn command(sql: &str) -> String {
+        classify(sql).command
+    }
+
+    fn found(kind: DestructiveKind, tables: &[&str]) -> Destructive {
+        Destructive {
+            kind,
+            tables: tables.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn classifies_reads() {
+        for sql in [
+            "SELECT 1",
+            "  values (1), (2)",
+            "TABLE accounts",
+            "SHOW search_path",
+            "(SELECT 1) UNION (SELECT 2)",
+            "SELECT $1::int",
+            "EXPLAIN DELETE FROM t",
+            "EXPLAIN (ANALYZE false) DELETE FROM t",
+            "EXPLAIN (COSTS, ANALYZE off) UPDATE t SET a = 1",
+            "COPY (SELECT 1) TO STDOUT",
+            "WITH x AS (SELECT 1) SELECT * FROM x",
+            "",
+            ";",
+        ] {
+            assert_eq!(kind(sql), StatementKind::Read, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn skips_comments_and_literals() {
+        for sql in [
+            "SELECT '; DELETE FROM t'",
+            "SELECT 'it''s; DROP TABLE t'",
+            r"SELECT E'it\'s; DROP TABLE t'",
+            "SELECT \"DROP\" FROM t",
+            "SELECT $$; DROP TABLE t$$",
+            "SELECT $fn$ $$; DROP TABLE t; $$ $fn$",
+            "-- DELETE FROM t\nSELECT 1",
+            "/* outer /* DELETE FROM t; */ still a comment; DROP TABLE t */ SELECT 1",
+        ] {
+            assert_eq!(kind(sql), StatementKind::Read, "{}", sql);
+        }
+        // Without E, a backslash doesn't escape the quote
+        assert_eq!(kind(r"SELECT 'a\'; DELETE FROM t"), StatementKind::Write);
+    }
+
+    #[test]
+    fn classifies_writes() {
+        assert_eq!(kind("INSERT INTO t VALUES (1)"), StatementKind::Write);
+        assert_eq!(kind("COPY t FROM STDIN"), StatementKind::Write);
+        assert_eq!(kind("CALL refresh()"), StatementKind::Write);
+        assert_eq!(kind("DO $$ BEGIN END $$"), StatementKind::Write);
+        assert_eq!(
+            command("WITH d AS (DELETE FROM t RETURNING *) SELECT * FROM d"),
+            "WITH DELETE"
+        );
+        assert_eq!(
+            command("WITH x AS (SELECT 1) INSERT INTO t SELECT * FROM x"),
+            "WITH INSERT"
+        );
+        assert_eq!(
+            command("EXPLAIN ANALYZE DELETE FROM t"),
+            "EXPLAIN ANALYZE DELETE"
+        );
+        assert_eq!(
+            kind("EXPLAIN (ANALYZE) UPDATE t SET a = 1"),
+            StatementKind::Write
+        );
+        assert_eq!(
+            kind("EXPLAIN ANALYZE VERBOSE SELECT 1"),
+            StatementKind::Read
+        );
+    }
+
+    #[test]
+    fn classifies_ddl_and_admin() {
+        let into = classify("SELECT * INTO copy FROM t");
+        assert_eq!(
+            (into.kind, into.command.as_str()),
+            (StatementKind::Ddl, "SELECT INTO")
+        );
+        // INTO inside parentheses isn't SELECT INTO
+        assert_eq!(
+            kind("SELECT (SELECT 1) FROM t WHERE a IN (SELECT b FROM u)"),
+            StatementKind::Read
+        );
+        assert_eq!(kind("CREATE TABLE t (id int)"), StatementKind::Ddl);
+        let system = classify("ALTER SYSTEM SET work_mem = '64MB'");
+        assert_eq!(
+            (system.kind, system.command.as_str()),
+            (StatementKind::Admin, "ALTER SYSTEM")
+        );
+        assert_eq!(kind("COPY t TO '/tmp/t.csv'"), StatementKind::Admin);
+        assert_eq!(kind("VACUUM t"), StatementKind::Admin);
+        assert_eq!(kind("$1"), StatementKind::Admin);
+        // The most privileged statement wins
+        assert_eq!(
+            command("SELECT 1; DROP TABLE t; INSERT INTO u VALUES (1)"),
+            "DROP"
+        );
+    }
+
+    #[test]
+    fn finds_destructive_statements() {
+        use DestructiveKind::*;
+        assert_eq!(
+            destructive("DELETE FROM Public.Users"),
+            [found(UnboundedDelete, &["public.users"])]
+        );
+        assert!(destructive("DELETE FROM t WHERE id = 1").is_empty());
+        assert_eq!(
+            destructive("UPDATE ONLY s.t SET a = 1"),
+            [found(UnboundedUpdate, &["s.t"])]
+        );
+        // A WHERE inside a subquery doesn't bound the UPDATE
+        assert_eq!(
+            destructive("UPDATE t SET a = (SELECT b FROM u WHERE u.id = 1)"),
+            [found(UnboundedUpdate, &["t"])]
+        );
+        assert_eq!(
+            destructive("TRUNCATE TABLE a, \"B\".c *, d"),
+            [found(Truncate, &["a", "\"B\".c", "d"])]
+        );
+        assert_eq!(
+            destructive("DROP TABLE IF EXISTS a, b"),
+            [found(Drop, &["a", "b"])]
+        );
+        assert_eq!(destructive("DROP INDEX i"), [found(Drop, &[])]);
+        assert_eq!(
+            destructive("WITH d AS (DELETE FROM t) SELECT 1; EXPLAIN ANALYZE DELETE FROM u"),
+            [
+                found(UnboundedDelete, &["t"]),
+                found(UnboundedDelete, &["u"])
+            ]
+        );
+        assert!(destructive("WITH d AS (DELETE FROM t WHERE a) SELECT 1").is_empty());
+        assert!(destructive("EXPLAIN DELETE FROM t").is_empty());
+        assert!(destructive("SELECT 'DROP TABLE t'").is_empty());
+    }
+
+    #[test]
+    fn finds_dropped_columns() {
+        use DestructiveKind::*;
+        assert_eq!(
+            destructive("ALTER TABLE t DROP COLUMN c"),
+            [found(DropColumn, &["t"])]
+        );
+        assert_eq!(
+            destructive("ALTER TABLE IF EXISTS t DROP c"),
+            [found(DropColumn, &["t"])]
+        );
+        for sql in [
+            "ALTER TABLE t DROP CONSTRAINT k",
+            "ALTER TABLE t ALTER c DROP DEFAULT",
+            "ALTER TABLE t ALTER c DROP NOT NULL",
+            "ALTER TABLE t ALTER c DROP IDENTITY",
+            "ALTER TABLE t ALTER c DROP EXPRESSION",
+            "ALTER TABLE t ADD CHECK (a IN (SELECT 1))",
+        ] {
+            assert!(destructive(sql).is_empty(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn finds_transaction_control() {
+        let control = |sql| transaction_control(sql).map(|c| c.command);
+        assert_eq!(control("COMMIT").as_deref(), Some("COMMIT"));
+        assert_eq!(control("commit and chain").as_deref(), Some("COMMIT"));
+        assert_eq!(
+            control("COMMIT PREPARED 'x'").as_deref(),
+            Some("COMMIT PREPARED")
+        );
+        assert_eq!(
+            control("START TRANSACTION READ ONLY").as_deref(),
+            Some("START TRANSACTION")
+        );
+        assert_eq!(
+            control("PREPARE TRANSACTION 'x'").as_deref(),
+            Some("PREPARE TRANSACTION")
+        );
+        assert_eq!(
+            control("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE").as_deref(),
+            Some("SET TRANSACTION")
+        );
+        assert_eq!(
+            control("SELECT 1; ROLLBACK TO SAVEPOINT a").as_deref(),
+            Some("ROLLBACK")
+        );
+        for sql in [
+            "BEGIN",
+            "END",
+            "ABORT",
+            "SAVEPOINT a",
+            "RELEASE SAVEPOINT a",
+        ] {
+            assert!(control(sql).is_some(), "{}", sql);
+        }
+        for sql in [
+            "PREPARE q AS SELECT 1",
+            "SET search_path = app",
+            "DO $$ BEGIN COMMIT; END $$",
+            "/* BEGIN */ SELECT 1",
+            "SELECT 'COMMIT'",
+        ] {
+            assert_eq!(control(sql), None, "{}", sql);
+        }
+    }
+}
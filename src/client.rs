@@ -1,150 +1,1268 @@
 //! PostgreSQL client with connection pooling.
 
 use anyhow::{Context, Result};
-use deadpool_postgres::{Config, Pool, Runtime};
+use deadpool_postgres::{
+    Config, Connect, GenericClient, Manager, Object, Pool, PoolError, Runtime, TimeoutType,
+};
+use futures_util::{StreamExt, TryStreamExt};
+use postgres_native_tls::MakeTlsConnector;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use tokio_postgres::types::ToSql;
-use tokio_postgres::NoTls;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_postgres::{IsolationLevel, NoTls};
+
+use crate::cancel::{InFlight, Limits, Running};
+use crate::classify::{classify, destructive, transaction_control, Classification, StatementKind};
+use crate::copy::{self, ExportOptions, ImportOptions};
+use crate::cursor::{Cursor, Cursors};
+use crate::decode::{self, RawValue};
+use crate::error::{
+    self, Attempts, ConfirmationRequired, FailedStatement, GuardedStatement, Refusal,
+};
+use crate::guard::{Confirmations, GuardConfig, CONFIRMATION_TTL};
+use crate::hosts::{self, HostConnector, HostPort, LastRejection};
+use crate::libpq::{self, Params};
+use crate::params::{as_refs, bind_params};
+use crate::pool::{ConnectSettings, PoolSettings};
+use crate::reconnect::Availability;
+use crate::session::{Session, Sessions, MAX_SESSIONS};
+use crate::tls::{self, SslMode};
+use crate::types::type_name;
 
 /// Connection configuration for PostgreSQL.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ConnectionConfig {
+    /// Host name or Unix socket directory, or several `host[:port]` separated by
+    /// commas, tried in turn.
     pub host: String,
+    /// Port of hosts that don't name one.
     pub port: u16,
     pub user: String,
     pub password: Option<String>,
     pub database: String,
-    pub ssl: bool,
+    #[serde(default)]
+    pub sslmode: SslMode,
+    /// PEM file with trusted root certificates, or `system`.
+    pub sslrootcert: Option<String>,
+    /// PEM client certificate for certificate authentication.
+    pub sslcert: Option<String>,
+    /// PKCS#8 PEM private key for `sslcert`.
+    pub sslkey: Option<String>,
+    /// Refuse anything but reads, and run every transaction read-only.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Destructive statements that `execute` and `transaction` run only once confirmed.
+    #[serde(default)]
+    pub guard: GuardConfig,
+    /// Start even if the database can't be reached, and connect in the background.
+    #[serde(default)]
+    pub lazy: bool,
+    /// Standbys that serve reads: `host[:port]` sharing everything else, or a URL.
+    #[serde(default)]
+    pub replicas: Vec<String>,
+    /// Most replay lag a replica may have and still serve reads.
+    pub max_lag_ms: Option<u64>,
+    #[serde(flatten)]
+    pub connect: ConnectSettings,
+    #[serde(default)]
+    pub pool: PoolSettings,
 }
 
 impl ConnectionConfig {
     /// Parse a DATABASE_URL into ConnectionConfig.
     pub fn from_url(url: &str) -> Result<Self> {
-        let parsed = url::Url::parse(url).context("Invalid DATABASE_URL format")?;
+        Self::from_params(&libpq::url_params(url, "url")?)
+    }
+
+    /// Build config from libpq connection parameters, e.g. `host` and `sslmode`.
+    ///
+    /// `host` may list hosts and socket directories, with a port for each in `port`.
+    pub fn from_params(params: &Params) -> Result<Self> {
+        let param = |key: &str| params.get(key).map(str::to_string);
+        let (host, port) = hosts::with_ports(
+            params.get("host").unwrap_or("localhost"),
+            params.get("port").unwrap_or("5432"),
+        )?;
 
         Ok(Self {
-            host: parsed.host_str().unwrap_or("localhost").to_string(),
-            port: parsed.port().unwrap_or(5432),
-            user: parsed.username().to_string(),
-            password: parsed.password().map(|s| s.to_string()),
-            database: parsed.path().trim_start_matches('/').to_string(),
-            ssl: parsed.query_pairs().any(|(k, v)| k == "sslmode" && v != "disable"),
+            host,
+            port,
+            user: param("user").unwrap_or_default(),
+            password: param("password"),
+            database: param("dbname").unwrap_or_default(),
+            sslmode: param("sslmode")
+                .map(|m| m.parse())
+                .transpose()?
+                .unwrap_or_default(),
+            sslrootcert: param("sslrootcert"),
+            sslcert: param("sslcert"),
+            sslkey: param("sslkey"),
+            read_only: false,
+            guard: GuardConfig::default(),
+            lazy: false,
+            replicas: Vec::new(),
+            max_lag_ms: None,
+            connect: ConnectSettings::from_query(param)?,
+            pool: PoolSettings::from_query(param)?,
         })
     }
 
-    /// Build config from libpq-style environment variables.
-    pub fn from_env() -> Result<Self> {
-        Ok(Self {
-            host: std::env::var("PGHOST").unwrap_or_else(|_| "localhost".into()),
-            port: std::env::var("PGPORT")
-                .ok()
-                .and_then(|p| p.parse().ok())
-                .unwrap_or(5432),
-            user: std::env::var("PGUSER").unwrap_or_else(|_| "postgres".into()),
-            password: std::env::var("PGPASSWORD").ok(),
-            database: std::env::var("PGDATABASE").unwrap_or_else(|_| "postgres".into()),
-            ssl: std::env::var("PGSSLMODE")
-                .map(|m| m != "disable")
-                .unwrap_or(false),
-        })
+    /// The configuration of replica `spec`, which only serves reads.
+    pub fn replica(&self, spec: &str) -> Result<Self> {
+        let mut replica = if spec.contains("://") {
+            let mut replica = Self::from_url(spec)?;
+            // Settings the URL leaves out come from the primary
+            let mut connect = self.connect.clone();
+            connect.merge(&replica.connect);
+            replica.connect = connect;
+            let mut pool = self.pool.clone();
+            pool.merge(&replica.pool);
+            replica.pool = pool;
+            replica
+        } else {
+            let (host, port) = match spec.rsplit_once(':') {
+                Some((host, port)) => (
+                    host,
+                    port.parse()
+                        .with_context(|| format!("Invalid port in replica '{}'", spec))?,
+                ),
+                None => (spec, self.port),
+            };
+            Self {
+                host: host.to_string(),
+                port,
+                ..self.clone()
+            }
+        };
+        replica.read_only = true;
+        replica.replicas = Vec::new();
+        Ok(replica)
+    }
+
+    /// Every host to try, in the order given.
+    pub fn hosts(&self) -> Result<Vec<HostPort>> {
+        hosts::parse_hosts(&self.host, self.port)
+    }
+
+    /// The user to connect as; the login name when none is configured, as in libpq.
+    pub fn effective_user(&self) -> String {
+        match self.user.as_str() {
+            "" => std::env::var("USER").unwrap_or_default(),
+            user => user.to_string(),
+        }
+    }
+
+    /// The database to connect to; named after the user when none is configured.
+    pub fn effective_database(&self) -> String {
+        match self.database.as_str() {
+            "" => self.effective_user(),
+            database => database.to_string(),
+        }
+    }
+
+    /// The connection as a libpq URL, with its password masked.
+    pub fn masked_url(&self) -> Result<String> {
+        let hosts = self.hosts()?;
+        let mut query: Vec<(String, String)> = Vec::new();
+        let authority = if hosts.iter().any(HostPort::is_socket) {
+            // Socket directories go in parameters, e.g. `postgres:///app?host=/tmp`
+            let ports: Vec<String> = hosts.iter().map(|host| host.port.to_string()).collect();
+            let hosts: Vec<&str> = hosts.iter().map(|host| host.host.as_str()).collect();
+            query.push(("host".into(), hosts.join(",")));
+            query.push(("port".into(), ports.join(",")));
+            String::new()
+        } else {
+            let hosts: Vec<String> = hosts
+                .iter()
+                .map(|host| {
+                    if host.host.contains(':') {
+                        host.to_string()
+                    } else {
+                        format!("{}:{}", hosts::percent_encode(&host.host), host.port)
+                    }
+                })
+                .collect();
+            hosts.join(",")
+        };
+        let userinfo = match (self.user.as_str(), &self.password) {
+            ("", None) => String::new(),
+            (user, None) => format!("{}@", hosts::percent_encode(user)),
+            (user, Some(_)) => format!("{}:***@", hosts::percent_encode(user)),
+        };
+
+        query.push(("sslmode".into(), self.sslmode.as_str().into()));
+        let files = [
+            ("sslrootcert", &self.sslrootcert),
+            ("sslcert", &self.sslcert),
+            ("sslkey", &self.sslkey),
+        ];
+        for (key, file) in files {
+            if let Some(file) = file {
+                query.push((key.into(), file.clone()));
+            }
+        }
+        for (key, value) in self.connect.params().into_iter().chain(self.pool.params()) {
+            let value = match value {
+                Value::String(s) => s,
+                value => value.to_string(),
+            };
+            query.push((key, value));
+        }
+        let query: Vec<String> = query
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, hosts::percent_encode(value)))
+            .collect();
+
+        Ok(format!(
+            "postgres://{}{}/{}?{}",
+            userinfo,
+            authority,
+            hosts::percent_encode(&self.database),
+            query.join("&")
+        ))
+    }
+}
+
+/// How result rows are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RowFormat {
+    /// One object per row, keyed by column name.
+    #[default]
+    Objects,
+    /// One array per row, in `columns` order; keeps duplicate column names.
+    Arrays,
+}
+
+impl FromStr for RowFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "objects" => Ok(RowFormat::Objects),
+            "arrays" => Ok(RowFormat::Arrays),
+            _ => anyhow::bail!("Invalid row_format '{}' (expected objects or arrays)", s),
+        }
     }
 }
 
+/// Rows per page for cursors opened without `max_rows`.
+pub const DEFAULT_CURSOR_PAGE: i64 = 1000;
+
+/// One statement of a `PostgresClient::transaction`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TxStatement {
+    pub sql: String,
+    /// Positional parameters bound to `$1`, `$2`, ...
+    #[serde(default)]
+    pub params: Vec<Value>,
+    /// Return the statement's rows (e.g. from `RETURNING`).
+    #[serde(default)]
+    pub returning: bool,
+}
+
+impl TxStatement {
+    /// Parse a statement given either as an SQL string or as an object.
+    pub fn from_json(value: &Value) -> Result<Self> {
+        match value {
+            Value::String(sql) => Ok(Self {
+                sql: sql.clone(),
+                params: Vec::new(),
+                returning: false,
+            }),
+            _ => serde_json::from_value(value.clone())
+                .context("Statements must be SQL strings or {sql, params, returning} objects"),
+        }
+    }
+}
+
+/// Transaction characteristics; unset fields keep the server defaults.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionOptions {
+    pub isolation: Option<IsolationLevel>,
+    pub read_only: Option<bool>,
+    pub deferrable: Option<bool>,
+    /// Replay the transaction on transient failures.
+    pub retry: Option<RetryPolicy>,
+    /// Roll back instead of committing.
+    pub dry_run: bool,
+    pub limits: Limits,
+}
+
+/// Options for `PostgresClient::execute`.
+#[derive(Debug, Clone, Default)]
+pub struct ExecuteOptions {
+    /// Return the statement's rows (e.g. from `RETURNING`).
+    pub returning: bool,
+    /// Run in a transaction that is always rolled back.
+    pub dry_run: bool,
+    pub limits: Limits,
+}
+
+/// Issued first in dry runs, so deferred constraints are checked before the rollback.
+const CHECK_CONSTRAINTS_NOW: &str = "SET CONSTRAINTS ALL IMMEDIATE";
+
+/// When and how often to replay a transaction that failed with a transient error.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first.
+    pub max_attempts: u32,
+    /// Backoff before the first retry; doubles with every retry after that.
+    pub base_delay_ms: u64,
+    /// Upper bound for a single backoff.
+    pub max_delay_ms: u64,
+    /// SQLSTATEs worth retrying.
+    pub sqlstates: Vec<String>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_ms: 50,
+            max_delay_ms: 2000,
+            // serialization_failure, deadlock_detected
+            sqlstates: vec!["40001".to_string(), "40P01".to_string()],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Parse `true` (the default policy), `false`, or a policy object.
+    pub fn from_json(value: &Value) -> Result<Option<Self>> {
+        let policy: Self = match value {
+            Value::Null | Value::Bool(false) => return Ok(None),
+            Value::Bool(true) => Self::default(),
+            _ => serde_json::from_value(value.clone()).context(
+                "retry must be a boolean or {max_attempts, base_delay_ms, max_delay_ms, sqlstates}",
+            )?,
+        };
+        if policy.max_attempts == 0 {
+            anyhow::bail!("retry.max_attempts must be at least 1");
+        }
+        Ok(Some(policy))
+    }
+
+    /// Whether `e` failed with one of the retryable SQLSTATEs.
+    fn should_retry(&self, e: &anyhow::Error) -> bool {
+        error::db_error(e).is_some_and(|db| self.retries(db.code().code()))
+    }
+
+    fn retries(&self, sqlstate: &str) -> bool {
+        self.sqlstates.iter().any(|s| s == sqlstate)
+    }
+
+    /// Delay before retry number `retry` (1-based): exponential, with the upper
+    /// half jittered so that colliding transactions don't retry in lockstep.
+    fn backoff(&self, retry: u32, rng: &mut impl Rng) -> Duration {
+        let ceiling = self
+            .base_delay_ms
+            .saturating_mul(1u64 << (retry - 1).min(32))
+            .min(self.max_delay_ms);
+        let jitter = rng.random_range(0..=ceiling / 2);
+        Duration::from_millis(ceiling - ceiling / 2 + jitter)
+    }
+}
+
+/// Parse an isolation level such as `serializable` or `repeatable read`.
+pub fn parse_isolation(s: &str) -> Result<IsolationLevel> {
+    match s.to_ascii_lowercase().replace(['_', '-'], " ").as_str() {
+        "read uncommitted" => Ok(IsolationLevel::ReadUncommitted),
+        "read committed" => Ok(IsolationLevel::ReadCommitted),
+        "repeatable read" => Ok(IsolationLevel::RepeatableRead),
+        "serializable" => Ok(IsolationLevel::Serializable),
+        _ => anyhow::bail!(
+            "Invalid isolation '{}' (expected read committed, repeatable read or serializable)",
+            s
+        ),
+    }
+}
+
+/// Result shaping options for `PostgresClient::query`.
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// Return column descriptions instead of bare names.
+    pub column_metadata: bool,
+    /// Return rows as objects or positional arrays.
+    pub row_format: RowFormat,
+    /// Stop after this many rows (the page size in cursor mode).
+    pub max_rows: Option<i64>,
+    /// Keep a server-side cursor open for `fetch` instead of returning every row.
+    pub cursor: bool,
+    pub limits: Limits,
+}
+
 /// PostgreSQL client with connection pooling.
 pub struct PostgresClient {
     pool: Pool,
     config: ConnectionConfig,
+    cursors: Cursors,
+    sessions: Sessions,
+    confirmations: Confirmations,
+    in_flight: InFlight,
+    availability: Availability,
+    /// The host last connected to, of those configured.
+    server: Arc<Mutex<HostPort>>,
+    /// Why the last failed connection found no server matching target_session_attrs.
+    rejected: LastRejection,
+    /// What the pool's connections negotiate TLS with, for cancelling their queries.
+    tls: Option<MakeTlsConnector>,
 }
 
 impl PostgresClient {
     /// Create a new PostgreSQL client with connection pool.
     pub async fn new(config: ConnectionConfig) -> Result<Self> {
         let mut cfg = Config::new();
-        cfg.host = Some(config.host.clone());
-        cfg.port = Some(config.port);
         cfg.user = Some(config.user.clone());
         cfg.password = config.password.clone();
         cfg.dbname = Some(config.database.clone());
+        // 0 means no limit, as in libpq
+        cfg.connect_timeout = config
+            .connect
+            .connect_timeout
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs);
+        cfg.keepalives = config.connect.keepalives;
+        cfg.keepalives_idle = config.connect.keepalives_idle.map(Duration::from_secs);
+        cfg.application_name = config.connect.application_name.clone();
+        let mut options: Vec<&str> = config.connect.options.iter().map(String::as_str).collect();
+        if config.read_only {
+            // The server enforces what classification can't see, e.g. functions that write.
+            // Last, so it wins over the configured options.
+            options.push("-c default_transaction_read_only=on");
+        }
+        cfg.options = (!options.is_empty()).then(|| options.join(" "));
+
+        // What the driver defaults to, for looking up the password file
+        let user = config.effective_user();
+        let database = config.effective_database();
 
-        // Create pool - using NoTls for simplicity, can add TLS support later
-        let pool = cfg
-            .create_pool(Some(Runtime::Tokio1), NoTls)
+        // One driver config per host; the connector picks the host for each connection
+        let hosts = config
+            .hosts()?
+            .into_iter()
+            .map(|host| {
+                cfg.host = Some(host.host.clone());
+                cfg.port = Some(host.port);
+                // As in libpq, TLS doesn't apply to Unix sockets
+                let sslmode = if host.is_socket() {
+                    SslMode::Disable
+                } else {
+                    config.sslmode
+                };
+                cfg.ssl_mode = Some(sslmode.pool_mode());
+                if config.password.is_none() {
+                    cfg.password = libpq::password(&host, &database, &user);
+                }
+                Ok((host, cfg.get_pg_config()?))
+            })
+            .collect::<Result<Vec<_>>>()
+            .context("Invalid connection settings")?;
+        let attrs = config.connect.target_session_attrs.unwrap_or_default();
+        let load_balance = config.connect.load_balance_hosts.unwrap_or_default();
+        let tls = tls::make_connector(&config)?;
+        let (pool, server, rejected) = match tls.clone() {
+            Some(tls) => Self::create_pool(
+                &config,
+                HostConnector::new(hosts, attrs, load_balance, config.read_only, tls),
+            ),
+            None => Self::create_pool(
+                &config,
+                HostConnector::new(hosts, attrs, load_balance, config.read_only, NoTls),
+            ),
+        }?;
+
+        Ok(Self {
+            pool,
+            config,
+            cursors: Cursors::default(),
+            sessions: Sessions::default(),
+            confirmations: Confirmations::default(),
+            in_flight: InFlight::default(),
+            availability: Availability::default(),
+            server,
+            rejected,
+            tls,
+        })
+    }
+
+    fn create_pool<T>(
+        config: &ConnectionConfig,
+        connector: HostConnector<T>,
+    ) -> Result<(Pool, Arc<Mutex<HostPort>>, LastRejection)>
+    where
+        HostConnector<T>: Connect + 'static,
+    {
+        let server = connector.current();
+        let rejected = connector.rejected();
+        let pg_config = connector.first_config();
+        let manager = Manager::from_connect(pg_config, connector, config.pool.manager_config());
+        let pool = Pool::builder(manager)
+            .config(config.pool.pool_config()?)
+            .runtime(Runtime::Tokio1)
+            .build()
             .context("Failed to create connection pool")?;
+        Ok((pool, server, rejected))
+    }
+
+    /// A connection from the pool; fails fast while the database is unreachable.
+    async fn connection(&self) -> Result<Object> {
+        self.availability.check()?;
+        self.pool.get().await.map_err(|e| {
+            // A busy pool isn't an outage, but failing to open a connection is
+            let outage = matches!(
+                e,
+                PoolError::Backend(_) | PoolError::Timeout(TimeoutType::Create)
+            );
+            let rejected = match e {
+                PoolError::Backend(_) => self.rejected.lock().unwrap().take(),
+                _ => None,
+            };
+            let e = match rejected {
+                Some(rejected) => anyhow::Error::new(rejected),
+                None => anyhow::Error::new(e),
+            };
+            if outage {
+                self.availability.lost(format!("{:#}", e));
+            }
+            e.context("Failed to get connection")
+        })
+    }
+
+    /// Reconnect in the background whenever the database becomes unreachable.
+    pub async fn reconnect(&self) {
+        self.availability
+            .reconnect(|| async {
+                let client = self.pool.get().await?;
+                client.simple_query("SELECT 1").await?;
+                // Connections the server closed while it was away
+                self.pool.retain(|client, _| !client.is_closed());
+                Ok(())
+            })
+            .await
+    }
+
+    /// Record that the database couldn't be reached, e.g. at a lazy startup.
+    pub fn mark_unavailable(&self, error: &anyhow::Error) {
+        self.availability.lost(format!("{:#}", error));
+    }
+
+    /// The current outage, or `None` while the database is reachable.
+    pub fn outage(&self) -> Option<Value> {
+        self.availability.outage()
+    }
+
+    /// On a read-only connection, refuse anything but a read.
+    fn require_writable(&self, sql: &str) -> std::result::Result<(), Refusal> {
+        if !self.config.read_only {
+            return Ok(());
+        }
+        let classification = classify(sql);
+        if classification.kind == StatementKind::Read {
+            return Ok(());
+        }
+        Err(Refusal::read_only(classification))
+    }
+
+    /// On a read-only connection, refuse to start a read-write transaction.
+    fn require_read_only_mode(&self, options: &TransactionOptions) -> Result<()> {
+        if self.config.read_only && options.read_only == Some(false) {
+            anyhow::bail!("The connection is read-only; read_only: false isn't allowed");
+        }
+        Ok(())
+    }
 
-        Ok(Self { pool, config })
+    /// Refuse guarded destructive statements unless `confirm` is the token issued
+    /// for them.
+    ///
+    /// The refusal estimates the rows at stake and carries a new token.
+    pub async fn require_confirmation(
+        &self,
+        statements: &[TxStatement],
+        confirm: Option<&str>,
+    ) -> Result<()> {
+        if !self.config.guard.is_enabled() {
+            return Ok(());
+        }
+        let mut guarded = Vec::new();
+        for (i, statement) in statements.iter().enumerate() {
+            for found in destructive(&statement.sql) {
+                if self.config.guard.guards(found.kind) {
+                    guarded.push((i, found));
+                }
+            }
+        }
+        if guarded.is_empty() {
+            return Ok(());
+        }
+        if let Some(token) = confirm {
+            return self.confirmations.redeem(token, statements);
+        }
+
+        let client = self.connection().await?;
+        let mut refused = Vec::new();
+        for (i, found) in guarded {
+            let mut estimated_rows = None;
+            for table in &found.tables {
+                if let Some(rows) = estimate_rows(&client, table).await? {
+                    estimated_rows = Some(estimated_rows.unwrap_or(0) + rows);
+                }
+            }
+            refused.push(GuardedStatement {
+                statement: i,
+                destructive: found.kind,
+                tables: found.tables,
+                estimated_rows,
+            });
+        }
+        let estimated_rows = refused.iter().filter_map(|s| s.estimated_rows).sum();
+
+        Err(ConfirmationRequired {
+            refused: "confirmation_required",
+            message: format!(
+                "Refusing {} destructive statement(s) affecting an estimated {} row(s) without confirmation",
+                refused.len(),
+                estimated_rows
+            ),
+            hint: format!(
+                "Resend the same request with confirm set to the confirmation_token within {}s",
+                CONFIRMATION_TTL.as_secs()
+            ),
+            confirmation_token: self.confirmations.issue(statements),
+            expires_in_ms: CONFIRMATION_TTL.as_millis() as u64,
+            estimated_rows,
+            statements: refused,
+        }
+        .into())
+    }
+
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
+    /// Whether no cursor or session is open, so the pool can be closed.
+    pub fn is_idle(&self) -> bool {
+        self.cursors.is_empty() && self.sessions.is_empty()
+    }
+
+    /// The host last connected to; the first one configured until a connection is made.
+    pub fn server(&self) -> HostPort {
+        self.server.lock().unwrap().clone()
     }
 
     /// Get connection info for health checks.
     pub fn connection_info(&self) -> String {
         format!(
-            "{}@{}:{}/{}",
-            self.config.user, self.config.host, self.config.port, self.config.database
+            "{}@{}/{}",
+            self.config.user,
+            self.server(),
+            self.config.database
         )
     }
 
+    /// Pool settings in effect, and how many connections are open, idle and awaited.
+    pub fn pool_status(&self) -> Value {
+        let status = self.pool.status();
+        let mut pool = self.config.pool.resolved(status.max_size);
+        pool["size"] = json!(status.size);
+        pool["available"] = json!(status.available);
+        pool["waiting"] = json!(status.waiting);
+        pool
+    }
+
+    /// Replay lag of a standby, or an error if the server isn't one.
+    pub async fn replay_lag(&self) -> Result<Duration> {
+        let client = self.connection().await?;
+        // A standby that has replayed everything it received isn't behind, however
+        // long ago the last transaction was
+        let row = client
+            .query_one(
+                "SELECT pg_is_in_recovery(), \
+                 CASE WHEN pg_last_wal_receive_lsn() = pg_last_wal_replay_lsn() THEN 0 \
+                 ELSE EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp()) * 1000 \
+                 END::float8",
+                &[],
+            )
+            .await?;
+        if !row.get::<_, bool>(0) {
+            anyhow::bail!("Not a standby (pg_is_in_recovery() is false)");
+        }
+        let lag_ms: Option<f64> = row.get(1);
+        let lag_ms = lag_ms.ok_or_else(|| anyhow::anyhow!("Nothing replayed yet"))?;
+        Ok(Duration::from_millis(lag_ms.max(0.0) as u64))
+    }
+
     /// Test database connectivity.
     pub async fn ping(&self) -> Result<bool> {
-        let client = self.pool.get().await.context("Failed to get connection")?;
+        let client = self.connection().await?;
         let row = client.query_one("SELECT 1", &[]).await?;
         let val: i32 = row.get(0);
         Ok(val == 1)
     }
 
     /// Execute a SQL query and return results as JSON.
-    pub async fn query(&self, sql: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Value> {
-        let client = self.pool.get().await.context("Failed to get connection")?;
-        let stmt = client.prepare(sql).await.context("Failed to prepare query")?;
-        let rows = client.query(&stmt, params).await.context("Query failed")?;
+    ///
+    /// `params` are bound to `$1`, `$2`, ... using the types the server reports for them.
+    /// With `max_rows`, rows are pulled through a portal so the rest of the result is
+    /// never sent, and `truncated` says whether anything was left over. A lock timeout
+    /// runs the query in a transaction too.
+    pub async fn query(
+        &self,
+        sql: &str,
+        params: &[Value],
+        options: &QueryOptions,
+    ) -> Result<Value> {
+        require_read(sql, QUERY_HINT)?;
+        let running = self.in_flight.start(&options.limits)?;
+        if options.cursor {
+            if options.limits.lock_timeout.is_some() {
+                anyhow::bail!("lock_timeout_ms can't be used with cursor");
+            }
+            return running
+                .run(self.open_cursor(sql, params, options, &running))
+                .await;
+        }
 
-        // Get column names
-        let columns: Vec<&str> = stmt.columns().iter().map(|c| c.name()).collect();
+        running
+            .run(async {
+                let mut client = self.connection().await?;
+                running.attach(&client, self.tls.as_ref())?;
+                let set_local = options.limits.set_local();
+                if options.max_rows.is_none() && set_local.is_none() {
+                    let stmt = client
+                        .prepare(sql)
+                        .await
+                        .context("Failed to prepare query")?;
+                    let params = bind_params(params, stmt.params())?;
+                    let rows = client
+                        .query(&stmt, &as_refs(&params))
+                        .await
+                        .context("Query failed")?;
+                    return query_result(&client, &stmt, &rows, None, options).await;
+                }
 
-        // Convert rows to JSON
-        let mut results = Vec::new();
-        for row in rows {
-            let mut obj = serde_json::Map::new();
-            for (i, col) in columns.iter().enumerate() {
-                let value = row_value_to_json(&row, i)?;
-                obj.insert(col.to_string(), value);
-            }
-            results.push(Value::Object(obj));
+                let tx = client
+                    .transaction()
+                    .await
+                    .context("Failed to start transaction")?;
+                // Before preparing, which already takes locks
+                if let Some(set_local) = &set_local {
+                    tx.batch_execute(set_local).await?;
+                }
+                let stmt = tx.prepare(sql).await.context("Failed to prepare query")?;
+                let params = bind_params(params, stmt.params())?;
+                let portal = tx
+                    .bind(&stmt, &as_refs(&params))
+                    .await
+                    .context("Query failed")?;
+                // A portal limit of 0 fetches every row
+                let limit = options.max_rows.map_or(0, |max_rows| {
+                    i32::try_from(max_rows.saturating_add(1)).unwrap_or(i32::MAX)
+                });
+                let mut rows = tx
+                    .query_portal(&portal, limit)
+                    .await
+                    .context("Query failed")?;
+                tx.commit().await.context("Failed to commit transaction")?;
+                let truncated = options.max_rows.map(|max_rows| {
+                    let truncated = rows.len() as i64 > max_rows;
+                    rows.truncate(max_rows as usize);
+                    truncated
+                });
+
+                query_result(&client, &stmt, &rows, truncated, options).await
+            })
+            .await
+    }
+
+    /// Declare a cursor for `sql` on a reserved connection and return its first page.
+    async fn open_cursor(
+        &self,
+        sql: &str,
+        params: &[Value],
+        options: &QueryOptions,
+        running: &Running,
+    ) -> Result<Value> {
+        let conn = self.connection().await?;
+        running.attach(&conn, self.tls.as_ref())?;
+        let page_size = options.max_rows.unwrap_or(DEFAULT_CURSOR_PAGE);
+        let (mut cursor, stmt) =
+            Cursor::open(conn, sql, params, options.row_format, page_size).await?;
+
+        let page = async {
+            let rows = cursor.fetch(page_size).await?;
+            let columns = columns_json(cursor.client()?, &stmt, options).await?;
+            Ok::<_, anyhow::Error>((format_rows(&rows, options.row_format)?, columns))
         }
+        .await;
+        let (rows, columns) = match page {
+            Ok(page) => page,
+            Err(e) => {
+                cursor.close(false).await;
+                return Err(e);
+            }
+        };
+        let has_more = rows.len() as i64 == page_size;
+
+        let cursor_id = if has_more {
+            Some(self.cursors.insert(cursor))
+        } else {
+            cursor.close(true).await;
+            None
+        };
 
         Ok(json!({
-            "rows": results,
-            "row_count": results.len(),
+            "cursor_id": cursor_id,
+            "row_count": rows.len(),
+            "rows": rows,
             "columns": columns,
+            "has_more": has_more,
+        }))
+    }
+
+    /// Fetch the next `count` rows (default: the cursor's page size) from an open cursor.
+    ///
+    /// The cursor is closed once it runs out of rows.
+    pub async fn fetch(&self, cursor_id: &str, count: Option<i64>) -> Result<Value> {
+        let cursor = self.cursors.get(cursor_id)?;
+        let mut cursor = cursor.lock().await;
+        let count = count.unwrap_or(cursor.page_size);
+
+        let rows = match cursor.fetch(count).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                self.cursors.remove(cursor_id);
+                cursor.close(false).await;
+                return Err(e);
+            }
+        };
+        let has_more = rows.len() as i64 == count;
+        if !has_more {
+            self.cursors.remove(cursor_id);
+            cursor.close(true).await;
+        }
+
+        Ok(json!({
+            "cursor_id": cursor_id,
+            "rows": format_rows(&rows, cursor.row_format)?,
+            "row_count": rows.len(),
+            "has_more": has_more,
+        }))
+    }
+
+    /// Close a cursor and release its connection.
+    pub async fn close_cursor(&self, cursor_id: &str) -> Result<Value> {
+        let cursor = self
+            .cursors
+            .remove(cursor_id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown or expired cursor: {}", cursor_id))?;
+        cursor.lock().await.close(true).await;
+
+        Ok(json!({
+            "cursor_id": cursor_id,
+            "closed": true,
+        }))
+    }
+
+    /// Roll back cursors that have been idle too long.
+    pub async fn expire_cursors(&self) {
+        for (id, cursor) in self.cursors.take_expired() {
+            tracing::info!("Closing idle cursor {}", id);
+            cursor.lock().await.close(false).await;
+        }
+    }
+
+    /// Whether a request named `request_id` is running here.
+    pub fn is_running(&self, request_id: &str) -> bool {
+        self.in_flight.contains(request_id)
+    }
+
+    /// Whether cursor `cursor_id` is open here.
+    pub fn has_cursor(&self, cursor_id: &str) -> bool {
+        self.cursors.get(cursor_id).is_ok()
+    }
+
+    /// Cancel the running request named `request_id`.
+    ///
+    /// The request fails with the server's `query_canceled` error shortly after.
+    pub fn cancel(&self, request_id: &str) -> Result<Value> {
+        self.in_flight.cancel(request_id)?;
+
+        Ok(json!({
+            "request_id": request_id,
+            "cancelled": true,
         }))
     }
 
     /// Execute a non-SELECT statement (INSERT, UPDATE, DELETE).
-    pub async fn execute(&self, sql: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Value> {
-        let client = self.pool.get().await.context("Failed to get connection")?;
-        let rows_affected = client.execute(sql, params).await.context("Execute failed")?;
+    ///
+    /// A dry run executes inside a transaction that is always rolled back, so it
+    /// refuses transaction control; a lock timeout needs a transaction too.
+    pub async fn execute(
+        &self,
+        sql: &str,
+        params: &[Value],
+        options: &ExecuteOptions,
+    ) -> Result<Value> {
+        self.require_writable(sql)?;
+        if options.dry_run {
+            require_no_transaction_control(sql, DRY_RUN_HINT)?;
+        }
+        let running = self.in_flight.start(&options.limits)?;
+        running
+            .run(async {
+                let mut client = self.connection().await?;
+                running.attach(&client, self.tls.as_ref())?;
+                let set_local = options.limits.set_local();
+                if !options.dry_run && set_local.is_none() {
+                    return run_statement(&client, sql, params, options.returning).await;
+                }
+
+                let tx = client
+                    .transaction()
+                    .await
+                    .context("Failed to start transaction")?;
+                if let Some(set_local) = &set_local {
+                    tx.batch_execute(set_local).await?;
+                }
+                if !options.dry_run {
+                    let result = run_statement(&tx, sql, params, options.returning).await?;
+                    tx.commit().await.context("Failed to commit transaction")?;
+                    return Ok(result);
+                }
+                tx.batch_execute(CHECK_CONSTRAINTS_NOW).await?;
+                let mut result = run_statement(&tx, sql, params, options.returning).await?;
+                tx.rollback()
+                    .await
+                    .context("Failed to roll back transaction")?;
+                result["dry_run"] = json!(true);
+                Ok(result)
+            })
+            .await
+    }
+
+    /// Start an interactive transaction on a pinned connection and return its session id.
+    pub async fn begin(
+        &self,
+        options: &TransactionOptions,
+        idle_timeout: Duration,
+    ) -> Result<Value> {
+        self.require_read_only_mode(options)?;
+        let limit = MAX_SESSIONS
+            .min(self.pool.status().max_size.saturating_sub(1))
+            .max(1);
+        let too_many = || {
+            anyhow::anyhow!(
+                "Too many open sessions ({}); commit or roll back one first",
+                limit
+            )
+        };
+        if self.sessions.len() >= limit {
+            return Err(too_many());
+        }
+
+        let conn = self.connection().await?;
+        let session = Session::begin(conn, options, idle_timeout).await?;
+        match self.sessions.insert(session, limit) {
+            Ok(session_id) => Ok(json!({
+                "session_id": session_id,
+                "idle_timeout_ms": idle_timeout.as_millis() as u64,
+            })),
+            Err(mut session) => {
+                let _ = session.rollback().await;
+                Err(too_many())
+            }
+        }
+    }
+
+    /// Run a query inside a session.
+    ///
+    /// `max_rows` stops reading after that many rows; cursors aren't available.
+    pub async fn session_query(
+        &self,
+        session_id: &str,
+        sql: &str,
+        params: &[Value],
+        options: &QueryOptions,
+    ) -> Result<Value> {
+        require_no_transaction_control(sql, SESSION_HINT)?;
+        require_read(sql, QUERY_HINT)?;
+        if options.cursor {
+            anyhow::bail!("cursor can't be used inside a session (use max_rows)");
+        }
+        require_no_lock_timeout(&options.limits)?;
+        let session = self.sessions.get(session_id)?;
+        let mut session = session.lock().await;
+        let running = self.in_flight.start(&options.limits)?;
+        let result = running
+            .run(async {
+                let client = session.client()?;
+                running.attach(client, self.tls.as_ref())?;
+                let stmt = client
+                    .prepare(sql)
+                    .await
+                    .context("Failed to prepare query")?;
+                let params = bind_params(params, stmt.params())?;
+                let (rows, truncated) = match options.max_rows {
+                    Some(max_rows) => {
+                        let stream = client
+                            .query_raw(&stmt, as_refs(&params))
+                            .await
+                            .context("Query failed")?;
+                        let mut rows: Vec<_> = stream
+                            .take(max_rows as usize + 1)
+                            .try_collect()
+                            .await
+                            .context("Query failed")?;
+                        let truncated = rows.len() as i64 > max_rows;
+                        rows.truncate(max_rows as usize);
+                        (rows, Some(truncated))
+                    }
+                    None => {
+                        let rows = client
+                            .query(&stmt, &as_refs(&params))
+                            .await
+                            .context("Query failed")?;
+                        (rows, None)
+                    }
+                };
+                query_result(client, &stmt, &rows, truncated, options).await
+            })
+            .await;
+        session.track(result)
+    }
+
+    /// Execute a statement inside a session.
+    ///
+    /// Transaction control is refused: only `commit`, `rollback` and `savepoint`
+    /// keep the session in step with its transaction.
+    pub async fn session_execute(
+        &self,
+        session_id: &str,
+        sql: &str,
+        params: &[Value],
+        options: &ExecuteOptions,
+    ) -> Result<Value> {
+        if options.dry_run {
+            anyhow::bail!(
+                "dry_run can't be used inside a session (roll back to a savepoint instead)"
+            );
+        }
+        require_no_transaction_control(sql, SESSION_HINT)?;
+        self.require_writable(sql)?;
+        require_no_lock_timeout(&options.limits)?;
+        let session = self.sessions.get(session_id)?;
+        let mut session = session.lock().await;
+        let running = self.in_flight.start(&options.limits)?;
+        let result = running
+            .run(async {
+                let client = session.client()?;
+                running.attach(client, self.tls.as_ref())?;
+                run_statement(client, sql, params, options.returning).await
+            })
+            .await;
+        session.track(result)
+    }
+
+    /// Set a savepoint in a session.
+    pub async fn savepoint(&self, session_id: &str, name: &str) -> Result<Value> {
+        let session = self.sessions.get(session_id)?;
+        session.lock().await.savepoint(name).await?;
+
+        Ok(json!({
+            "session_id": session_id,
+            "savepoint": name,
+        }))
+    }
+
+    /// Forget a savepoint, keeping what was done since it was set.
+    pub async fn release_savepoint(&self, session_id: &str, name: &str) -> Result<Value> {
+        let session = self.sessions.get(session_id)?;
+        session.lock().await.release_savepoint(name).await?;
+
+        Ok(json!({
+            "session_id": session_id,
+            "released": name,
+        }))
+    }
+
+    /// Commit a session's transaction and end the session.
+    pub async fn commit(&self, session_id: &str) -> Result<Value> {
+        let session = self.sessions.get(session_id)?;
+        let mut session = session.lock().await;
+        let committed = session.commit().await;
+        if !session.is_open() {
+            self.sessions.remove(session_id)?;
+        }
+        committed?;
+
+        Ok(json!({
+            "session_id": session_id,
+            "committed": true,
+        }))
+    }
+
+    /// Roll back a session to `savepoint`, or roll back and end it.
+    pub async fn rollback(&self, session_id: &str, savepoint: Option<&str>) -> Result<Value> {
+        let session = self.sessions.get(session_id)?;
+        let mut session = session.lock().await;
+        if let Some(name) = savepoint {
+            session.rollback_to(name).await?;
+            return Ok(json!({
+                "session_id": session_id,
+                "rolled_back_to": name,
+            }));
+        }
+
+        self.sessions.remove(session_id)?;
+        session.rollback().await?;
 
         Ok(json!({
-            "rows_affected": rows_affected,
+            "session_id": session_id,
+            "rolled_back": true,
         }))
     }
 
+    /// Roll back sessions that have been idle too long.
+    pub async fn expire_sessions(&self) {
+        for (id, session) in self.sessions.take_expired() {
+            let mut session = session.lock().await;
+            tracing::info!(
+                "Rolling back session {} after {:?} idle",
+                id,
+                session.idle_timeout()
+            );
+            if let Err(e) = session.rollback().await {
+                tracing::warn!("Failed to roll back session {}: {:#}", id, e);
+            }
+        }
+    }
+
     /// Execute multiple statements in a transaction.
-    pub async fn transaction(&self, statements: &[String]) -> Result<Value> {
-        let mut client = self.pool.get().await.context("Failed to get connection")?;
-        let tx = client.transaction().await.context("Failed to start transaction")?;
+    ///
+    /// Statements with `returning` report their rows; the rest report `rows_affected`.
+    /// With a retry policy the whole list is replayed in a new transaction after a
+    /// retryable failure, and the result reports the number of `attempts`. A dry run
+    /// refuses transaction control, checks deferred constraints after each statement
+    /// and then rolls back.
+    pub async fn transaction(
+        &self,
+        statements: &[TxStatement],
+        options: &TransactionOptions,
+    ) -> Result<Value> {
+        self.require_read_only_mode(options)?;
+        for (i, statement) in statements.iter().enumerate() {
+            let refused = self.require_writable(&statement.sql).and_then(|()| {
+                if options.dry_run {
+                    require_no_transaction_control(&statement.sql, DRY_RUN_HINT)?;
+                }
+                Ok(())
+            });
+            if let Err(refusal) = refused {
+                return Err(Refusal {
+                    statement: Some(i),
+                    ..refusal
+                }
+                .into());
+            }
+        }
+
+        let running = self.in_flight.start(&options.limits)?;
+        running
+            .run(self.retry_transaction(statements, options, &running))
+            .await
+    }
+
+    /// Run `transaction`, replaying it under its retry policy.
+    async fn retry_transaction(
+        &self,
+        statements: &[TxStatement],
+        options: &TransactionOptions,
+        running: &Running,
+    ) -> Result<Value> {
+        let Some(retry) = &options.retry else {
+            return self.run_transaction(statements, options, running).await;
+        };
+
+        let mut attempt = 1;
+        loop {
+            match self.run_transaction(statements, options, running).await {
+                Ok(mut result) => {
+                    result["attempts"] = json!(attempt);
+                    return Ok(result);
+                }
+                Err(e) if attempt < retry.max_attempts && retry.should_retry(&e) => {
+                    let delay = retry.backoff(attempt, &mut rand::rng());
+                    tracing::info!(
+                        "Transaction attempt {} failed, retrying in {:?}: {:#}",
+                        attempt,
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.context(Attempts(attempt))),
+            }
+        }
+    }
+
+    /// One attempt at `transaction`, on a fresh connection.
+    async fn run_transaction(
+        &self,
+        statements: &[TxStatement],
+        options: &TransactionOptions,
+        running: &Running,
+    ) -> Result<Value> {
+        let mut client = self.connection().await?;
+        running.attach(&client, self.tls.as_ref())?;
+        let mut builder = client.build_transaction();
+        if let Some(isolation) = options.isolation {
+            builder = builder.isolation_level(isolation);
+        }
+        if let Some(read_only) = options.read_only {
+            builder = builder.read_only(read_only);
+        }
+        if let Some(deferrable) = options.deferrable {
+            builder = builder.deferrable(deferrable);
+        }
+        let tx = builder
+            .start()
+            .await
+            .context("Failed to start transaction")?;
+        if let Some(set_local) = options.limits.set_local() {
+            tx.batch_execute(&set_local).await?;
+        }
+        if options.dry_run {
+            tx.batch_execute(CHECK_CONSTRAINTS_NOW).await?;
+        }
 
         let mut results = Vec::new();
-        for (i, sql) in statements.iter().enumerate() {
-            let rows_affected = tx
-                .execute(sql.as_str(), &[])
+        for (i, statement) in statements.iter().enumerate() {
+            let mut result =
+                run_statement(&tx, &statement.sql, &statement.params, statement.returning)
+                    .await
+                    .with_context(|| FailedStatement {
+                        index: Some(i),
+                        sql: statement.sql.clone(),
+                    })?;
+            result["statement"] = json!(i);
+            results.push(result);
+        }
+
+        if options.dry_run {
+            tx.rollback()
                 .await
-                .with_context(|| format!("Statement {} failed", i))?;
-            results.push(json!({
-                "statement": i,
-                "rows_affected": rows_affected,
+                .context("Failed to roll back transaction")?;
+            return Ok(json!({
+                "committed": false,
+                "dry_run": true,
+                "statements": results,
             }));
         }
-
         tx.commit().await.context("Failed to commit transaction")?;
 
         Ok(json!({
@@ -153,6 +1271,35 @@ impl PostgresClient {
         }))
     }
 
+    /// Export the result of `sql` to a file with COPY.
+    pub async fn export(&self, sql: &str, path: &str, options: &ExportOptions) -> Result<Value> {
+        require_read(
+            sql,
+            "Export a SELECT, or run the statement with postgres.execute",
+        )?;
+        let client = self.connection().await?;
+        copy::export(&client, sql, path, options).await
+    }
+
+    /// Load a CSV or NDJSON file into a table with COPY.
+    pub async fn import(
+        &self,
+        path: &str,
+        schema: &str,
+        table: &str,
+        options: &ImportOptions,
+    ) -> Result<Value> {
+        if self.config.read_only {
+            return Err(Refusal::read_only(Classification {
+                kind: StatementKind::Write,
+                command: "COPY".to_string(),
+            })
+            .into());
+        }
+        let client = self.connection().await?;
+        copy::import(&client, path, schema, table, options).await
+    }
+
     /// List tables in a schema.
     pub async fn list_tables(&self, schema: &str) -> Result<Value> {
         let sql = r#"
@@ -166,23 +1313,32 @@ impl PostgresClient {
             ORDER BY table_name
         "#;
 
-        self.query(sql, &[&schema]).await
+        self.query(sql, &[json!(schema)], &QueryOptions::default())
+            .await
     }
 
     /// Get table schema (columns, types, constraints).
     pub async fn table_schema(&self, table: &str, schema: &str) -> Result<Value> {
+        // Same shape as `describe_columns`, plus the column default.
         let columns_sql = r#"
             SELECT
-                column_name,
-                data_type,
-                is_nullable,
-                column_default,
-                character_maximum_length,
-                numeric_precision,
-                numeric_scale
-            FROM information_schema.columns
-            WHERE table_schema = $1 AND table_name = $2
-            ORDER BY ordinal_position
+                a.attname AS name,
+                CASE WHEN t.typcategory = 'A' THEN et.typname || '[]' ELSE t.typname END AS type,
+                a.atttypid AS type_oid,
+                a.atttypmod AS type_modifier,
+                format_type(a.atttypid, a.atttypmod) AS declared_type,
+                NOT a.attnotnull AS nullable,
+                a.attrelid AS table_oid,
+                a.attnum AS column_id,
+                pg_get_expr(d.adbin, d.adrelid) AS "default"
+            FROM pg_attribute a
+            JOIN pg_class c ON c.oid = a.attrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            JOIN pg_type t ON t.oid = a.atttypid
+            LEFT JOIN pg_type et ON et.oid = t.typelem
+            LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+            WHERE n.nspname = $1 AND c.relname = $2 AND a.attnum > 0 AND NOT a.attisdropped
+            ORDER BY a.attnum
         "#;
 
         let constraints_sql = r#"
@@ -205,27 +1361,27 @@ impl PostgresClient {
             WHERE schemaname = $1 AND tablename = $2
         "#;
 
-        let client = self.pool.get().await.context("Failed to get connection")?;
+        let client = self.connection().await?;
 
         // Get columns
         let columns = {
             let stmt = client.prepare(columns_sql).await?;
             let rows = client.query(&stmt, &[&schema, &table]).await?;
-            rows_to_json(&rows, &stmt)?
+            rows_to_json(&rows)?
         };
 
         // Get constraints
         let constraints = {
             let stmt = client.prepare(constraints_sql).await?;
             let rows = client.query(&stmt, &[&schema, &table]).await?;
-            rows_to_json(&rows, &stmt)?
+            rows_to_json(&rows)?
         };
 
         // Get indexes
         let indexes = {
             let stmt = client.prepare(indexes_sql).await?;
             let rows = client.query(&stmt, &[&schema, &table]).await?;
-            rows_to_json(&rows, &stmt)?
+            rows_to_json(&rows)?
         };
 
         Ok(json!({
@@ -248,12 +1404,12 @@ impl PostgresClient {
             ORDER BY schema_name
         "#;
 
-        self.query(sql, &[]).await
+        self.query(sql, &[], &QueryOptions::default()).await
     }
 
     /// Get database statistics.
     pub async fn stats(&self) -> Result<Value> {
-        let client = self.pool.get().await.context("Failed to get connection")?;
+        let client = self.connection().await?;
 
         // Database size
         let size_row = client
@@ -294,83 +1450,77 @@ impl PostgresClient {
     }
 }
 
-/// Convert a row value at index to JSON Value.
-fn row_value_to_json(row: &tokio_postgres::Row, idx: usize) -> Result<Value> {
-    use tokio_postgres::types::Type;
+/// Describe result columns: type, modifier, nullability and source table.
+///
+/// `nullable` reflects the source column's NOT NULL constraint and is `null`
+/// for computed columns, where it can't be known.
+async fn describe_columns(
+    client: &tokio_postgres::Client,
+    columns: &[tokio_postgres::Column],
+) -> Result<Vec<Value>> {
+    let type_oids: Vec<u32> = columns.iter().map(|c| c.type_().oid()).collect();
+    let modifiers: Vec<i32> = columns.iter().map(|c| c.type_modifier()).collect();
+    let table_oids: Vec<Option<u32>> = columns.iter().map(|c| c.table_oid()).collect();
+    let column_ids: Vec<Option<i16>> = columns.iter().map(|c| c.column_id()).collect();
 
-    let col = row.columns().get(idx).unwrap();
+    let rows = client
+        .query(
+            r#"
+            SELECT format_type(c.type_oid, c.type_modifier), NOT a.attnotnull
+            FROM unnest($1::oid[], $2::int4[], $3::oid[], $4::int2[])
+                WITH ORDINALITY AS c(type_oid, type_modifier, table_oid, column_id, ord)
+            LEFT JOIN pg_attribute a ON a.attrelid = c.table_oid AND a.attnum = c.column_id
+            ORDER BY c.ord
+            "#,
+            &[&type_oids, &modifiers, &table_oids, &column_ids],
+        )
+        .await
+        .context("Failed to describe columns")?;
 
-    // Handle NULL values
-    if row.try_get::<_, Option<&[u8]>>(idx).ok().flatten().is_none()
-        && row.try_get::<_, Option<String>>(idx).ok().flatten().is_none()
-        && row.try_get::<_, Option<i32>>(idx).ok().flatten().is_none()
-    {
-        // Try to get as Option<String> to check for NULL
-        if let Ok(None) = row.try_get::<_, Option<String>>(idx) {
-            return Ok(Value::Null);
-        }
-    }
+    Ok(columns
+        .iter()
+        .zip(rows)
+        .map(|(col, row)| {
+            json!({
+                "name": col.name(),
+                "type": type_name(col.type_()),
+                "type_oid": col.type_().oid(),
+                "type_modifier": col.type_modifier(),
+                "declared_type": row.get::<_, Option<String>>(0),
+                "nullable": row.get::<_, Option<bool>>(1),
+                "table_oid": col.table_oid(),
+                "column_id": col.column_id(),
+            })
+        })
+        .collect())
+}
 
-    match *col.type_() {
-        Type::BOOL => {
-            let v: Option<bool> = row.get(idx);
-            Ok(v.map(Value::Bool).unwrap_or(Value::Null))
-        }
-        Type::INT2 => {
-            let v: Option<i16> = row.get(idx);
-            Ok(v.map(|n| json!(n)).unwrap_or(Value::Null))
-        }
-        Type::INT4 => {
-            let v: Option<i32> = row.get(idx);
-            Ok(v.map(|n| json!(n)).unwrap_or(Value::Null))
-        }
-        Type::INT8 => {
-            let v: Option<i64> = row.get(idx);
-            Ok(v.map(|n| json!(n)).unwrap_or(Value::Null))
-        }
-        Type::FLOAT4 => {
-            let v: Option<f32> = row.get(idx);
-            Ok(v.map(|n| json!(n)).unwrap_or(Value::Null))
-        }
-        Type::FLOAT8 => {
-            let v: Option<f64> = row.get(idx);
-            Ok(v.map(|n| json!(n)).unwrap_or(Value::Null))
-        }
-        Type::JSON | Type::JSONB => {
-            let v: Option<Value> = row.get(idx);
-            Ok(v.unwrap_or(Value::Null))
-        }
-        Type::TIMESTAMPTZ | Type::TIMESTAMP => {
-            let v: Option<chrono::NaiveDateTime> = row.get(idx);
-            Ok(v.map(|dt: chrono::NaiveDateTime| json!(dt.to_string())).unwrap_or(Value::Null))
-        }
-        Type::DATE => {
-            let v: Option<chrono::NaiveDate> = row.get(idx);
-            Ok(v.map(|d: chrono::NaiveDate| json!(d.to_string())).unwrap_or(Value::Null))
-        }
-        Type::UUID => {
-            // UUID needs to be converted to string
-            let v: Option<String> = row.try_get(idx).ok().flatten();
-            Ok(v.map(|s| json!(s)).unwrap_or(Value::Null))
-        }
-        _ => {
-            // Default: try to get as string
-            let v: Option<String> = row.try_get(idx).ok().flatten();
-            Ok(v.map(|s| json!(s)).unwrap_or(Value::Null))
-        }
+/// Convert a row value at index to JSON Value.
+fn row_value_to_json(row: &tokio_postgres::Row, idx: usize) -> Result<Value> {
+    let col = &row.columns()[idx];
+
+    match row.try_get::<_, Option<RawValue>>(idx)? {
+        None => Ok(Value::Null),
+        Some(RawValue(raw)) => decode::to_json(col.type_(), raw).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to decode column \"{}\" ({}): {}",
+                col.name(),
+                type_name(col.type_()),
+                e
+            )
+        }),
     }
 }
 
 /// Convert rows to JSON array.
-fn rows_to_json(rows: &[tokio_postgres::Row], stmt: &tokio_postgres::Statement) -> Result<Vec<Value>> {
-    let columns: Vec<&str> = stmt.columns().iter().map(|c| c.name()).collect();
+fn rows_to_json(rows: &[tokio_postgres::Row]) -> Result<Vec<Value>> {
     let mut results = Vec::new();
 
     for row in rows {
         let mut obj = serde_json::Map::new();
-        for (i, col) in columns.iter().enumerate() {
+        for (i, col) in row.columns().iter().enumerate() {
             let value = row_value_to_json(row, i)?;
-            obj.insert(col.to_string(), value);
+            obj.insert(col.name().to_string(), value);
         }
         results.push(Value::Object(obj));
     }
@@ -378,6 +1528,151 @@ fn rows_to_json(rows: &[tokio_postgres::Row], stmt: &tokio_postgres::Statement)
     Ok(results)
 }
 
+/// Convert rows in the requested layout.
+fn format_rows(rows: &[tokio_postgres::Row], format: RowFormat) -> Result<Vec<Value>> {
+    match format {
+        RowFormat::Objects => rows_to_json(rows),
+        RowFormat::Arrays => rows_to_arrays(rows),
+    }
+}
+
+/// Hint for statements refused by `PostgresClient::query`.
+const QUERY_HINT: &str =
+    "Use postgres.execute or postgres.transaction for statements that change data";
+
+/// Hint for transaction control refused inside a session.
+const SESSION_HINT: &str =
+    "Use postgres.commit, postgres.rollback and postgres.savepoint, and postgres.begin's options for isolation";
+
+/// Hint for transaction control refused in a dry run.
+const DRY_RUN_HINT: &str = "A dry run always rolls back; leave out statements that end it early";
+
+/// Sessions run in one long transaction, where `SET LOCAL` would outlast the request.
+fn require_no_lock_timeout(limits: &Limits) -> Result<()> {
+    if limits.lock_timeout.is_some() {
+        anyhow::bail!(
+            "lock_timeout_ms can't be used inside a session (run SET LOCAL lock_timeout instead)"
+        );
+    }
+    Ok(())
+}
+
+/// Refuse statements that start or end the transaction the request runs in.
+fn require_no_transaction_control(sql: &str, hint: &str) -> std::result::Result<(), Refusal> {
+    match transaction_control(sql) {
+        Some(classification) => Err(Refusal::transaction_control(classification, hint)),
+        None => Ok(()),
+    }
+}
+
+/// Refuse anything but a read.
+fn require_read(sql: &str, hint: &str) -> std::result::Result<(), Refusal> {
+    let classification = classify(sql);
+    if classification.kind == StatementKind::Read {
+        return Ok(());
+    }
+    Err(Refusal::not_a_read(classification, hint))
+}
+
+/// Rows in `table` according to planner statistics, or live tuples before the
+/// first ANALYZE; `None` if there is no such table.
+async fn estimate_rows(client: &tokio_postgres::Client, table: &str) -> Result<Option<i64>> {
+    let row = client
+        .query_opt(
+            "SELECT CASE WHEN c.reltuples >= 0 THEN c.reltuples::bigint
+                         ELSE pg_stat_get_live_tuples(c.oid) END
+             FROM pg_class c
+             WHERE c.oid = to_regclass($1)",
+            &[&table],
+        )
+        .await
+        .context("Failed to estimate affected rows")?;
+    Ok(row.map(|row| row.get(0)))
+}
+
+/// The response for a query's `rows`, shaped by `options`.
+async fn query_result(
+    client: &tokio_postgres::Client,
+    stmt: &tokio_postgres::Statement,
+    rows: &[tokio_postgres::Row],
+    truncated: Option<bool>,
+    options: &QueryOptions,
+) -> Result<Value> {
+    let mut result = json!({
+        "rows": format_rows(rows, options.row_format)?,
+        "row_count": rows.len(),
+        "columns": columns_json(client, stmt, options).await?,
+    });
+    if let Some(truncated) = truncated {
+        result["truncated"] = json!(truncated);
+    }
+    Ok(result)
+}
+
+/// Run one non-SELECT statement and report the rows it affected, plus the rows
+/// themselves with `returning`.
+async fn run_statement<C: GenericClient>(
+    client: &C,
+    sql: &str,
+    params: &[Value],
+    returning: bool,
+) -> Result<Value> {
+    let stmt = client
+        .prepare(sql)
+        .await
+        .context("Failed to prepare statement")?;
+    let params = bind_params(params, stmt.params())?;
+    if returning {
+        let rows = client
+            .query(&stmt, &as_refs(&params))
+            .await
+            .context("Execute failed")?;
+        return Ok(json!({
+            "rows_affected": rows.len(),
+            "rows": rows_to_json(&rows)?,
+        }));
+    }
+    let rows_affected = client
+        .execute(&stmt, &as_refs(&params))
+        .await
+        .context("Execute failed")?;
+
+    Ok(json!({
+        "rows_affected": rows_affected,
+    }))
+}
+
+/// Column names, or full descriptions with `column_metadata`.
+async fn columns_json(
+    client: &tokio_postgres::Client,
+    stmt: &tokio_postgres::Statement,
+    options: &QueryOptions,
+) -> Result<Value> {
+    if options.column_metadata {
+        Ok(Value::Array(
+            describe_columns(client, stmt.columns()).await?,
+        ))
+    } else {
+        Ok(json!(stmt
+            .columns()
+            .iter()
+            .map(|c| c.name())
+            .collect::<Vec<_>>()))
+    }
+}
+
+/// Convert rows to JSON arrays of values, in column order.
+fn rows_to_arrays(rows: &[tokio_postgres::Row]) -> Result<Vec<Value>> {
+    rows.iter()
+        .map(|row| {
+            (0..row.len())
+                .map(|i| row_value_to_json(row, i))
+                .collect::<Result<Vec<_>>>()
+                .map(Value::Array)
+        })
+        .collect()
+}
+
 /// Format bytes to human-readable string.
 fn format_bytes(bytes: u64) -> String {
     const KB: u64 = 1024;
@@ -394,3 +1689,71 @@ fn format_bytes(bytes: u64) -> String {
         format!("{} bytes", bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn backoff_doubles_within_jitter_bounds() {
+        let policy = RetryPolicy::default();
+        let mut rng = StdRng::seed_from_u64(7);
+        for retry in 1..=64 {
+            let ceiling = (50u64 << (retry - 1).min(32)).min(2000);
+            for _ in 0..100 {
+                let delay = policy.backoff(retry, &mut rng).as_millis() as u64;
+                assert!(
+                    (ceiling - ceiling / 2..=ceiling).contains(&delay),
+                    "retry {}: {} ms outside {}..={}",
+                    retry,
+                    delay,
+                    ceiling - ceiling / 2,
+                    ceiling
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_without_delay() {
+        let policy = RetryPolicy {
+            base_delay_ms: 0,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(
+            policy.backoff(3, &mut StdRng::seed_from_u64(1)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn retries_listed_sqlstates() {
+        let policy = RetryPolicy::default();
+        assert!(policy.retries("40001"));
+        assert!(policy.retries("40P01"));
+        assert!(!policy.retries("23505"));
+        assert!(!policy.retries("57014"));
+
+        let policy = RetryPolicy::from_json(&json!({"sqlstates": ["55P03"]}))
+            .unwrap()
+            .unwrap();
+        assert!(policy.retries("55P03"));
+        assert!(!policy.retries("40001"));
+        assert_eq!(policy.max_attempts, 3);
+    }
+
+    #[test]
+    fn retry_policy_from_json() {
+        assert!(RetryPolicy::from_json(&json!(false)).unwrap().is_none());
+        assert_eq!(
+            RetryPolicy::from_json(&json!(true))
+                .unwrap()
+                .unwrap()
+                .sqlstates,
+            ["40001", "40P01"]
+        );
+        assert!(RetryPolicy::from_json(&json!({"max_attempts": 0})).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+//! The named connections a daemon serves, each with a pool opened on first use.
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::client::{ConnectionConfig, PostgresClient};
+use crate::replica::{Replica, Servers, DEFAULT_MAX_LAG, LAG_CHECK_INTERVAL};
+
+/// How long a pool may go unused before it is closed, unless configured.
+pub const DEFAULT_IDLE_POOL_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// A connection whose pools are open.
+struct Open {
+    servers: Arc<Servers>,
+    last_used: Instant,
+    /// Reconnection and replica lag checks, stopped when the pools are closed.
+    tasks: Vec<JoinHandle<()>>,
+}
+
+/// Every connection a daemon can serve, by name.
+pub struct Connections {
+    default: String,
+    configs: BTreeMap<String, ConnectionConfig>,
+    open: Mutex<HashMap<String, Open>>,
+    /// Close pools unused for this long; `None` keeps them open.
+    idle_timeout: Option<Duration>,
+}
+
+impl Connections {
+    /// `configs` must include `default`, the connection used when a request names none.
+    pub fn new(
+        default: String,
+        configs: BTreeMap<String, ConnectionConfig>,
+        idle_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            default,
+            configs,
+            open: Mutex::new(HashMap::new()),
+            idle_timeout,
+        }
+    }
+
+    pub fn default_name(&self) -> &str {
+        &self.default
+    }
+
+    /// The servers of connection `name`, or of the default connection, opening their
+    /// pools if needed.
+    ///
+    /// Must be called within the runtime, which runs reconnection and lag checks.
+    pub async fn get(&self, name: Option<&str>) -> Result<Arc<Servers>> {
+        let name = name.unwrap_or(&self.default);
+        if let Some(open) = self.open.lock().unwrap().get_mut(name) {
+            open.last_used = Instant::now();
+            return Ok(open.servers.clone());
+        }
+
+        let config = self.configs.get(name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown connection '{}' (postgres.connections lists them)",
+                name
+            )
+        })?;
+        let servers = Arc::new(open_servers(config).await?);
+
+        // Another request may have opened it meanwhile; theirs is kept
+        let mut open = self.open.lock().unwrap();
+        let open = open.entry(name.to_string()).or_insert_with(|| {
+            tracing::info!("Opening connection pool '{}'", name);
+            Open {
+                tasks: spawn_tasks(&servers),
+                servers,
+                last_used: Instant::now(),
+            }
+        });
+        Ok(open.servers.clone())
+    }
+
+    fn clients(&self) -> Vec<Arc<PostgresClient>> {
+        let open = self.open.lock().unwrap();
+        open.values()
+            .flat_map(|open| open.servers.clients().cloned())
+            .collect()
+    }
+
+    /// Release abandoned cursors and sessions, then close pools left idle.
+    pub async fn sweep(&self) {
+        for client in self.clients() {
+            client.expire_cursors().await;
+            client.expire_sessions().await;
+        }
+
+        let Some(idle_timeout) = self.idle_timeout else {
+            return;
+        };
+        self.open.lock().unwrap().retain(|name, open| {
+            // Open cursors and sessions live on the pools' connections
+            let keep = open.last_used.elapsed() < idle_timeout
+                || !open.servers.clients().all(|client| client.is_idle());
+            if !keep {
+                tracing::info!("Closing idle connection pool '{}'", name);
+                open.tasks.iter().for_each(JoinHandle::abort);
+            }
+            keep
+        });
+    }
+
+    /// Every connection, with its password masked and its pool if open.
+    pub fn list(&self) -> Result<Value> {
+        let open = self.open.lock().unwrap();
+        let connections = self
+            .configs
+            .iter()
+            .map(|(name, config)| {
+                let mut settings = serde_json::to_value(config)?;
+                if config.password.is_some() {
+                    settings["password"] = json!("***");
+                }
+                settings["replicas"] = json!(config
+                    .replicas
+                    .iter()
+                    .map(|spec| mask_password(spec))
+                    .collect::<Vec<_>>());
+                let servers = open.get(name).map(|open| &open.servers);
+                Ok(json!({
+                    "name": name,
+                    "default": *name == self.default,
+                    "settings": settings,
+                    "pool": servers.map(|servers| servers.primary.pool_status()),
+                    "replicas": servers.map(|servers| {
+                        servers.replicas.iter().map(Replica::status).collect::<Vec<_>>()
+                    }),
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(json!({
+            "connections": connections,
+            "default": self.default,
+            "idle_pool_timeout_ms": self.idle_timeout.map(|t| t.as_millis() as u64),
+        }))
+    }
+}
+
+/// Pools for a connection's primary and replicas.
+async fn open_servers(config: &ConnectionConfig) -> Result<Servers> {
+    let primary = Arc::new(PostgresClient::new(config.clone()).await?);
+    let max_lag = config
+        .max_lag_ms
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_MAX_LAG);
+    let mut replicas = Vec::with_capacity(config.replicas.len());
+    for spec in &config.replicas {
+        replicas.push(Replica::new(config.replica(spec)?, max_lag).await?);
+    }
+    Ok(Servers { primary, replicas })
+}
+
+/// Reconnect each server when it is lost, and keep measuring replica lag.
+fn spawn_tasks(servers: &Arc<Servers>) -> Vec<JoinHandle<()>> {
+    let mut tasks: Vec<JoinHandle<()>> = servers
+        .clients()
+        .map(|client| {
+            let reconnector = client.clone();
+            tokio::spawn(async move { reconnector.reconnect().await })
+        })
+        .collect();
+    if !servers.replicas.is_empty() {
+        let checker = servers.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut tick = tokio::time::interval(LAG_CHECK_INTERVAL);
+            loop {
+                tick.tick().await;
+                futures_util::future::join_all(checker.replicas.iter().map(Replica::check)).await;
+            }
+        }));
+    }
+    tasks
+}
+
+/// `url` with its password replaced by `***`; anything else is returned as is.
+pub fn mask_password(url: &str) -> String {
+    url::Url::parse(url)
+        .map(|mut u| {
+            if u.password().is_some() {
+                let _ = u.set_password(Some("***"));
+            }
+            u.to_string()
+        })
+        .unwrap_or_else(|_| url.to_string())
+}
@@ -0,0 +1,839 @@
+//! Bulk export and import through `COPY`.
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures_util::{pin_mut, SinkExt, TryStreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::str::FromStr;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+use crate::error::{FailedStatement, PgError};
+
+/// File formats for COPY.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyFormat {
+    Csv,
+    /// Postgres' text format: tab-separated, `\N` for NULL, backslash escapes.
+    Tsv,
+    /// One JSON object per line.
+    Ndjson,
+    /// Postgres' binary COPY format.
+    Binary,
+}
+
+impl CopyFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CopyFormat::Csv => "csv",
+            CopyFormat::Tsv => "tsv",
+            CopyFormat::Ndjson => "ndjson",
+            CopyFormat::Binary => "binary",
+        }
+    }
+}
+
+impl FromStr for CopyFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(CopyFormat::Csv),
+            "tsv" => Ok(CopyFormat::Tsv),
+            "ndjson" | "jsonl" => Ok(CopyFormat::Ndjson),
+            "binary" => Ok(CopyFormat::Binary),
+            _ => anyhow::bail!(
+                "Invalid format '{}' (expected csv, tsv, ndjson or binary)",
+                s
+            ),
+        }
+    }
+}
+
+/// Options for `export`.
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub format: CopyFormat,
+    /// Write a header line with column names (CSV and TSV).
+    pub header: bool,
+    /// CSV field delimiter (default `,`).
+    pub delimiter: Option<char>,
+    /// Replace the file if it already exists.
+    pub overwrite: bool,
+}
+
+/// What `import` does with rows that hit a unique constraint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnConflict {
+    /// Fail the import.
+    #[default]
+    Error,
+    /// Leave the existing row alone.
+    Skip,
+    /// Overwrite the existing row with the imported values.
+    Update,
+}
+
+impl FromStr for OnConflict {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(OnConflict::Error),
+            "skip" => Ok(OnConflict::Skip),
+            "update" => Ok(OnConflict::Update),
+            _ => anyhow::bail!(
+                "Invalid on_conflict '{}' (expected error, skip or update)",
+                s
+            ),
+        }
+    }
+}
+
+/// Which file fields go into which table columns.
+#[derive(Debug, Clone)]
+pub enum ColumnMapping {
+    /// Column names: the CSV fields in order, or the NDJSON keys to load.
+    Columns(Vec<String>),
+    /// Source field (CSV header or NDJSON key) to column. CSV fields not listed
+    /// keep their header name; NDJSON keys not listed are ignored.
+    Rename(Vec<(String, String)>),
+}
+
+impl ColumnMapping {
+    /// Parse the JSON `columns` parameter: an array of names or an object.
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let name = |v: &Value| {
+            v.as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow::anyhow!("Column names must be strings"))
+        };
+        match value {
+            Value::Array(names) => Ok(ColumnMapping::Columns(
+                names.iter().map(name).collect::<Result<_>>()?,
+            )),
+            Value::Object(map) => Ok(ColumnMapping::Rename(
+                map.iter()
+                    .map(|(k, v)| Ok((k.clone(), name(v)?)))
+                    .collect::<Result<_>>()?,
+            )),
+            _ => anyhow::bail!("columns must be an array of names or an object"),
+        }
+    }
+}
+
+impl FromStr for ColumnMapping {
+    type Err = anyhow::Error;
+
+    /// `a,b,c` lists columns; `src=a,other=b` renames source fields.
+    fn from_str(s: &str) -> Result<Self> {
+        let entries: Vec<&str> = s.split(',').map(str::trim).collect();
+        if entries.iter().all(|e| e.contains('=')) {
+            Ok(ColumnMapping::Rename(
+                entries
+                    .iter()
+                    .filter_map(|e| e.split_once('='))
+                    .map(|(from, to)| (from.to_string(), to.to_string()))
+                    .collect(),
+            ))
+        } else if entries.iter().any(|e| e.contains('=')) {
+            anyhow::bail!("Use either a column list (a,b) or renames (src=a,other=b), not both")
+        } else {
+            Ok(ColumnMapping::Columns(
+                entries.iter().map(|e| e.to_string()).collect(),
+            ))
+        }
+    }
+}
+
+/// Options for `import`.
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// `Csv` or `Ndjson`.
+    pub format: CopyFormat,
+    /// The CSV file starts with a header line.
+    pub header: bool,
+    /// CSV field delimiter (default `,`).
+    pub delimiter: Option<char>,
+    pub columns: Option<ColumnMapping>,
+    pub on_conflict: OnConflict,
+    /// Conflict target for `OnConflict::Update` (default: the primary key).
+    pub conflict_columns: Vec<String>,
+    /// Run the whole import in one transaction.
+    pub transaction: bool,
+    /// Empty the table before loading.
+    pub truncate: bool,
+    /// Load and report, then roll back.
+    pub dry_run: bool,
+}
+
+/// Quote an SQL identifier.
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Quote an SQL string literal.
+fn quote_literal(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// `SELECT *` over a table, for exporting a whole table.
+pub fn table_query(schema: &str, table: &str) -> String {
+    format!(
+        "SELECT * FROM {}.{}",
+        quote_ident(schema),
+        quote_ident(table)
+    )
+}
+
+/// Build the `COPY (query) TO STDOUT` statement for a format.
+fn export_statement(query: &str, options: &ExportOptions) -> Result<String> {
+    let query = query.trim().trim_end_matches(';');
+    let statement = match options.format {
+        CopyFormat::Csv => {
+            let delimiter = options.delimiter.unwrap_or(',');
+            if !delimiter.is_ascii() || delimiter == '\n' || delimiter == '\r' || delimiter == '"' {
+                anyhow::bail!("Invalid CSV delimiter {:?}", delimiter);
+            }
+            format!(
+                "COPY ({}) TO STDOUT WITH (FORMAT csv, HEADER {}, DELIMITER {})",
+                query,
+                options.header,
+                quote_literal(&delimiter.to_string())
+            )
+        }
+        // The header, if any, is written by `export` itself: text-format HEADER needs Postgres 15.
+        CopyFormat::Tsv => format!("COPY ({}) TO STDOUT", query),
+        // Control characters are always escaped in JSON, so with these QUOTE and DELIMITER
+        // values each JSON document comes through verbatim.
+        CopyFormat::Ndjson => format!(
+            "COPY (SELECT to_json(t) FROM ({}) t) TO STDOUT WITH (FORMAT csv, QUOTE E'\\x01', DELIMITER E'\\x02')",
+            query
+        ),
+        CopyFormat::Binary => format!("COPY ({}) TO STDOUT WITH (FORMAT binary)", query),
+    };
+    Ok(statement)
+}
+
+/// Stream the result of `query` into the file at `path`.
+///
+/// Nothing is buffered beyond one COPY chunk; the file is removed again on failure.
+pub async fn export(
+    client: &tokio_postgres::Client,
+    query: &str,
+    path: &str,
+    options: &ExportOptions,
+) -> Result<Value> {
+    let statement = export_statement(query, options)?;
+    let expanded = shellexpand::tilde(path).to_string();
+
+    let header = if options.format == CopyFormat::Tsv && options.header {
+        let stmt = client.prepare(query.trim().trim_end_matches(';')).await?;
+        let names: Vec<&str> = stmt.columns().iter().map(|c| c.name()).collect();
+        Some(format!("{}\n", names.join("\t")))
+    } else {
+        None
+    };
+
+    // Created in one step, so a file appearing meanwhile isn't replaced either
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .create_new(!options.overwrite)
+        .open(&expanded)
+        .await;
+    let file = match file {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            anyhow::bail!("{} already exists (set overwrite to replace it)", path)
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to create {}", path)),
+    };
+    let mut out = BufWriter::new(file);
+    let copied = async {
+        let mut counter = RowCounter::new(options.format);
+        let mut bytes = 0u64;
+        if let Some(header) = &header {
+            out.write_all(header.as_bytes()).await?;
+            bytes += header.len() as u64;
+        }
+
+        let stream =
+            client
+                .copy_out(statement.as_str())
+                .await
+                .with_context(|| FailedStatement {
+                    index: None,
+                    sql: statement.clone(),
+                })?;
+        pin_mut!(stream);
+        while let Some(chunk) = stream.try_next().await.with_context(|| FailedStatement {
+            index: None,
+            sql: statement.clone(),
+        })? {
+            counter.feed(&chunk)?;
+            out.write_all(&chunk).await?;
+            bytes += chunk.len() as u64;
+        }
+        out.flush().await?;
+
+        let mut rows = counter.rows;
+        if options.format == CopyFormat::Csv && options.header {
+            rows = rows.saturating_sub(1);
+        }
+        Ok::<_, anyhow::Error>((rows, bytes))
+    }
+    .await;
+
+    match copied {
+        Ok((rows, bytes)) => Ok(json!({
+            "path": expanded,
+            "format": options.format.as_str(),
+            "rows": rows,
+            "bytes": bytes,
+        })),
+        Err(e) => {
+            drop(out);
+            let _ = tokio::fs::remove_file(&expanded).await;
+            Err(e)
+        }
+    }
+}
+
+/// Bytes sent to the server per COPY message.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Load a CSV or NDJSON file into `schema.table` with COPY.
+///
+/// With `on_conflict` other than `Error`, rows are copied into a temporary
+/// staging table first and then inserted with `ON CONFLICT`. Failures report
+/// the file line and SQLSTATE where Postgres gives one.
+pub async fn import(
+    client: &tokio_postgres::Client,
+    path: &str,
+    schema: &str,
+    table: &str,
+    options: &ImportOptions,
+) -> Result<Value> {
+    if !matches!(options.format, CopyFormat::Csv | CopyFormat::Ndjson) {
+        anyhow::bail!("Import supports csv and ndjson files");
+    }
+    if options.dry_run && !options.transaction {
+        anyhow::bail!("dry_run needs the import to run in a transaction");
+    }
+    let delimiter = options.delimiter.unwrap_or(',');
+    if !delimiter.is_ascii() || matches!(delimiter, '\n' | '\r' | '"') {
+        anyhow::bail!("Invalid CSV delimiter {:?}", delimiter);
+    }
+
+    let expanded = shellexpand::tilde(path).to_string();
+    let file = File::open(&expanded).with_context(|| format!("Failed to open {}", path))?;
+    let mut reader = BufReader::with_capacity(CHUNK_SIZE, file);
+
+    let target = format!("{}.{}", quote_ident(schema), quote_ident(table));
+    let table_columns = table_columns(client, &target).await?;
+
+    // Work out the column list, consuming the CSV header or peeking at the first NDJSON line.
+    let mut first_line = None;
+    let (columns, keys) = match options.format {
+        CopyFormat::Csv => {
+            let header = if options.header {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                let line = line
+                    .trim_start_matches('\u{feff}')
+                    .trim_end_matches(['\r', '\n']);
+                Some(split_csv_line(line, delimiter))
+            } else {
+                None
+            };
+            let columns = match (&options.columns, header) {
+                (Some(ColumnMapping::Columns(names)), _) => names.clone(),
+                (Some(ColumnMapping::Rename(renames)), Some(header)) => {
+                    let renames: HashMap<&str, &str> = renames
+                        .iter()
+                        .map(|(from, to)| (from.as_str(), to.as_str()))
+                        .collect();
+                    header
+                        .iter()
+                        .map(|field| {
+                            renames
+                                .get(field.as_str())
+                                .unwrap_or(&field.as_str())
+                                .to_string()
+                        })
+                        .collect()
+                }
+                (None, Some(header)) => header,
+                (_, None) => {
+                    anyhow::bail!("A CSV file without a header needs columns listed in order")
+                }
+            };
+            (columns, Vec::new())
+        }
+        _ => match &options.columns {
+            Some(ColumnMapping::Columns(names)) => (names.clone(), names.clone()),
+            Some(ColumnMapping::Rename(renames)) => {
+                renames.iter().map(|(k, c)| (c.clone(), k.clone())).unzip()
+            }
+            None => {
+                let (number, line) = next_ndjson_line(&mut reader, 0)?
+                    .ok_or_else(|| anyhow::anyhow!("{} is empty", path))?;
+                let object = parse_ndjson(&line, number)?;
+                let keys: Vec<String> = object.keys().cloned().collect();
+                first_line = Some((number, line));
+                (keys.clone(), keys)
+            }
+        },
+    };
+    let unknown: Vec<&str> = columns
+        .iter()
+        .filter(|c| !table_columns.contains_key(c.as_str()))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        anyhow::bail!("Unknown column(s) in {}: {}", target, unknown.join(", "));
+    }
+    let column_list = columns
+        .iter()
+        .map(|c| quote_ident(c))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let staging = (options.on_conflict != OnConflict::Error)
+        .then(|| quote_ident(&format!("fgp_import_{}", uuid::Uuid::new_v4().simple())));
+    let conflict_target = match options.on_conflict {
+        OnConflict::Update if options.conflict_columns.is_empty() => {
+            primary_key(client, &target).await?
+        }
+        _ => options.conflict_columns.clone(),
+    };
+    if options.on_conflict == OnConflict::Update && conflict_target.is_empty() {
+        anyhow::bail!(
+            "on_conflict=update needs conflict_columns ({} has no primary key)",
+            target
+        );
+    }
+
+    if options.transaction {
+        client.batch_execute("BEGIN").await?;
+    }
+    let loaded = async {
+        if options.truncate {
+            client
+                .batch_execute(&format!("TRUNCATE {}", target))
+                .await?;
+        }
+        if let Some(staging) = &staging {
+            client
+                .batch_execute(&format!(
+                    "CREATE TEMP TABLE {} AS SELECT {} FROM {} WITH NO DATA",
+                    staging, column_list, target
+                ))
+                .await?;
+        }
+
+        let copy_into = staging.as_ref().unwrap_or(&target);
+        let statement = format!(
+            "COPY {} ({}) FROM STDIN WITH (FORMAT csv, DELIMITER {})",
+            copy_into,
+            column_list,
+            quote_literal(&delimiter.to_string())
+        );
+        let sink = client.copy_in::<_, Bytes>(statement.as_str()).await?;
+        pin_mut!(sink);
+
+        // Blank NDJSON lines that were skipped, to map COPY line numbers back to the file.
+        let mut skipped = Vec::new();
+        match options.format {
+            CopyFormat::Csv => {
+                let mut buf = vec![0u8; CHUNK_SIZE];
+                loop {
+                    let n = reader.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    sink.send(Bytes::copy_from_slice(&buf[..n])).await?;
+                }
+            }
+            _ => {
+                let is_array: Vec<bool> =
+                    columns.iter().map(|c| table_columns[c.as_str()]).collect();
+                let mut out = String::with_capacity(CHUNK_SIZE);
+                let mut number = 0;
+                let mut pending = first_line.take();
+                loop {
+                    let (line_number, line) = match pending.take() {
+                        Some(line) => line,
+                        None => match next_ndjson_line(&mut reader, number)? {
+                            Some(line) => line,
+                            None => break,
+                        },
+                    };
+                    skipped.extend(number + 1..line_number);
+                    number = line_number;
+
+                    let object = parse_ndjson(&line, number)?;
+                    for (i, (key, &is_array)) in keys.iter().zip(&is_array).enumerate() {
+                        if i > 0 {
+                            out.push(delimiter);
+                        }
+                        write_csv_field(
+                            &mut out,
+                            object.get(key).unwrap_or(&Value::Null),
+                            is_array,
+                        );
+                    }
+                    out.push('\n');
+                    if out.len() >= CHUNK_SIZE {
+                        sink.send(Bytes::from(std::mem::take(&mut out))).await?;
+                    }
+                }
+                if !out.is_empty() {
+                    sink.send(Bytes::from(out)).await?;
+                }
+            }
+        }
+        let copied = sink.as_mut().finish().await.map_err(|e| {
+            let line = copy_error_line(&e).map(|line| match options.format {
+                CopyFormat::Csv => line + options.header as u64,
+                _ => skipped
+                    .iter()
+                    .fold(line, |line, &s| if s <= line { line + 1 } else { line }),
+            });
+            import_error(e, line)
+        })?;
+
+        let loaded = match &staging {
+            Some(staging) => {
+                let action = match options.on_conflict {
+                    OnConflict::Update => format!(
+                        "({}) DO UPDATE SET {}",
+                        conflict_target
+                            .iter()
+                            .map(|c| quote_ident(c))
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        columns
+                            .iter()
+                            .map(|c| format!("{0} = EXCLUDED.{0}", quote_ident(c)))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    _ => "DO NOTHING".to_string(),
+                };
+                let inserted = client
+                    .execute(
+                        format!(
+                            "INSERT INTO {} ({}) SELECT {} FROM {} ON CONFLICT {}",
+                            target, column_list, column_list, staging, action
+                        )
+                        .as_str(),
+                        &[],
+                    )
+                    .await
+                    .map_err(|e| import_error(e, None))?;
+                client
+                    .batch_execute(&format!("DROP TABLE {}", staging))
+                    .await?;
+                inserted
+            }
+            None => copied,
+        };
+        Ok::<_, anyhow::Error>((copied, loaded))
+    }
+    .await;
+
+    match loaded {
+        Ok((copied, loaded)) => {
+            if options.dry_run {
+                client.batch_execute("ROLLBACK").await?;
+            } else if options.transaction {
+                client
+                    .batch_execute("COMMIT")
+                    .await
+                    .context("Failed to commit import")?;
+            }
+            let mut result = json!({
+                "table": format!("{}.{}", schema, table),
+                "format": options.format.as_str(),
+                "rows_loaded": loaded,
+            });
+            if options.on_conflict == OnConflict::Skip {
+                result["rows_skipped"] = json!(copied - loaded);
+            }
+            if options.dry_run {
+                result["dry_run"] = json!(true);
+            }
+            Ok(result)
+        }
+        Err(e) => {
+            let cleanup = match (options.transaction, &staging) {
+                (true, _) => Some("ROLLBACK".to_string()),
+                (false, Some(staging)) => Some(format!("DROP TABLE IF EXISTS {}", staging)),
+                (false, None) => None,
+            };
+            if let Some(cleanup) = cleanup {
+                if let Err(cleanup_err) = client.batch_execute(&cleanup).await {
+                    tracing::warn!("Failed to clean up after import: {}", cleanup_err);
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Columns of a table, mapped to whether they are arrays.
+async fn table_columns(
+    client: &tokio_postgres::Client,
+    target: &str,
+) -> Result<HashMap<String, bool>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT a.attname::text, t.typcategory = 'A'
+            FROM pg_attribute a
+            JOIN pg_type t ON t.oid = a.atttypid
+            WHERE a.attrelid = to_regclass($1) AND a.attnum > 0 AND NOT a.attisdropped
+            "#,
+            &[&target],
+        )
+        .await?;
+    if rows.is_empty() {
+        anyhow::bail!("Table {} not found", target);
+    }
+    Ok(rows.iter().map(|r| (r.get(0), r.get(1))).collect())
+}
+
+/// Primary key columns of a table, in key order.
+async fn primary_key(client: &tokio_postgres::Client, target: &str) -> Result<Vec<String>> {
+    let rows = client
+        .query(
+            r#"
+            SELECT a.attname::text
+            FROM pg_index i
+            CROSS JOIN LATERAL unnest(i.indkey) WITH ORDINALITY AS k(attnum, ord)
+            JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = k.attnum
+            WHERE i.indrelid = to_regclass($1) AND i.indisprimary
+            ORDER BY k.ord
+            "#,
+            &[&target],
+        )
+        .await?;
+    Ok(rows.iter().map(|r| r.get(0)).collect())
+}
+
+/// Next non-blank line after line `number`, with its 1-based line number.
+fn next_ndjson_line(reader: &mut impl BufRead, mut number: u64) -> Result<Option<(u64, String)>> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        number += 1;
+        if !line.trim().is_empty() {
+            return Ok(Some((number, line)));
+        }
+    }
+}
+
+fn parse_ndjson(line: &str, number: u64) -> Result<serde_json::Map<String, Value>> {
+    match serde_json::from_str(line) {
+        Ok(Value::Object(object)) => Ok(object),
+        Ok(_) => anyhow::bail!("Import failed at line {}: expected a JSON object", number),
+        Err(e) => anyhow::bail!("Import failed at line {}: invalid JSON: {}", number, e),
+    }
+}
+
+/// Split one CSV line (the header) into fields.
+fn split_csv_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            c if c == delimiter && !in_quotes => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Append a JSON value as a CSV field; NULL is an empty unquoted field.
+fn write_csv_field(out: &mut String, value: &Value, is_array: bool) {
+    let quoted = |out: &mut String, s: &str| {
+        out.push('"');
+        out.push_str(&s.replace('"', "\"\""));
+        out.push('"');
+    };
+    match value {
+        Value::Null => {}
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => out.push_str(&n.to_string()),
+        Value::String(s) => quoted(out, s),
+        Value::Array(items) if is_array => quoted(out, &array_literal(items)),
+        other => quoted(out, &other.to_string()),
+    }
+}
+
+/// Postgres array literal for a (possibly nested) JSON array.
+fn array_literal(items: &[Value]) -> String {
+    let quoted = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    let elements: Vec<String> = items
+        .iter()
+        .map(|item| match item {
+            Value::Null => "NULL".to_string(),
+            Value::Bool(_) | Value::Number(_) => item.to_string(),
+            Value::Array(inner) => array_literal(inner),
+            Value::String(s) => quoted(s),
+            Value::Object(_) => quoted(&item.to_string()),
+        })
+        .collect();
+    format!("{{{}}}", elements.join(","))
+}
+
+/// COPY line number from an error's context, e.g. `COPY users, line 12, column age: "x"`.
+fn copy_error_line(e: &tokio_postgres::Error) -> Option<u64> {
+    let context = e.as_db_error()?.where_()?;
+    let rest = &context[context.find(", line ")? + ", line ".len()..];
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+fn import_error(e: tokio_postgres::Error, line: Option<u64>) -> anyhow::Error {
+    match e.as_db_error() {
+        Some(db) => {
+            let mut error = PgError::from_db(db, None);
+            error.line = line;
+            anyhow::Error::new(error)
+        }
+        None => anyhow::Error::new(e).context("Import failed"),
+    }
+}
+
+/// Counts rows in a COPY stream as it goes by, chunk by chunk.
+struct RowCounter {
+    format: CopyFormat,
+    rows: u64,
+    /// CSV: inside a quoted field, where newlines don't end the row.
+    in_quotes: bool,
+    /// Binary: bytes of the next length/count word collected so far.
+    pending: Vec<u8>,
+    binary: BinaryState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryState {
+    /// Signature, flags and header extension length.
+    Header,
+    /// Skipping this many bytes of header extension or field data.
+    Skip(u64, u16),
+    /// Reading the field count of the next tuple.
+    Tuple,
+    /// Reading the length word of one of this many remaining fields.
+    Field(u16),
+    Trailer,
+}
+
+impl RowCounter {
+    fn new(format: CopyFormat) -> Self {
+        Self {
+            format,
+            rows: 0,
+            in_quotes: false,
+            pending: Vec::new(),
+            binary: BinaryState::Header,
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) -> Result<()> {
+        match self.format {
+            CopyFormat::Tsv | CopyFormat::Ndjson => {
+                self.rows += chunk.iter().filter(|&&b| b == b'\n').count() as u64;
+            }
+            CopyFormat::Csv => {
+                for &b in chunk {
+                    match b {
+                        b'"' => self.in_quotes = !self.in_quotes,
+                        b'\n' if !self.in_quotes => self.rows += 1,
+                        _ => {}
+                    }
+                }
+            }
+            CopyFormat::Binary => self.feed_binary(chunk)?,
+        }
+        Ok(())
+    }
+
+    fn feed_binary(&mut self, mut chunk: &[u8]) -> Result<()> {
+        const HEADER_LEN: usize = 11 + 4 + 4;
+        while !chunk.is_empty() {
+            if let BinaryState::Skip(n, fields) = self.binary {
+                let skipped = n.min(chunk.len() as u64);
+                chunk = &chunk[skipped as usize..];
+                self.binary = match n - skipped {
+                    0 if fields == 0 => BinaryState::Tuple,
+                    0 => BinaryState::Field(fields),
+                    left => BinaryState::Skip(left, fields),
+                };
+                continue;
+            }
+
+            let want = match self.binary {
+                BinaryState::Header => HEADER_LEN,
+                BinaryState::Tuple => 2,
+                BinaryState::Field(_) => 4,
+                BinaryState::Trailer => anyhow::bail!("Unexpected data after COPY trailer"),
+                BinaryState::Skip(..) => unreachable!(),
+            };
+            let take = (want - self.pending.len()).min(chunk.len());
+            self.pending.extend_from_slice(&chunk[..take]);
+            chunk = &chunk[take..];
+            if self.pending.len() < want {
+                break;
+            }
+
+            let word = std::mem::take(&mut self.pending);
+            self.binary = match self.binary {
+                BinaryState::Header => {
+                    let ext = u32::from_be_bytes(word[15..19].try_into().unwrap());
+                    match ext {
+                        0 => BinaryState::Tuple,
+                        n => BinaryState::Skip(n as u64, 0),
+                    }
+                }
+                BinaryState::Tuple => match i16::from_be_bytes([word[0], word[1]]) {
+                    -1 => BinaryState::Trailer,
+                    0 => {
+                        self.rows += 1;
+                        BinaryState::Tuple
+                    }
+                    n => {
+                        self.rows += 1;
+                        BinaryState::Field(n as u16)
+                    }
+                },
+                BinaryState::Field(fields) => {
+                    let remaining = fields - 1;
+                    match i32::from_be_bytes(word[..4].try_into().unwrap()) {
+                        len if len > 0 => BinaryState::Skip(len as u64, remaining),
+                        _ if remaining == 0 => BinaryState::Tuple,
+                        _ => BinaryState::Field(remaining),
+                    }
+                }
+                _ => unreachable!(),
+            };
+        }
+        Ok(())
+    }
+}
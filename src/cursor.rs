@@ -0,0 +1,193 @@
+//! Server-side cursors kept open across requests on a reserved connection.
+
+use anyhow::{Context, Result};
+use deadpool_postgres::Object;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_postgres::{Row, Statement};
+
+use crate::client::RowFormat;
+use crate::params::{as_refs, bind_params};
+
+/// Cursors untouched for this long are rolled back and their connection released.
+pub const CURSOR_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// An open `DECLARE ... CURSOR` inside a transaction on its own pooled connection.
+pub struct Cursor {
+    /// `None` once the cursor has been closed.
+    conn: Option<Object>,
+    name: String,
+    pub row_format: RowFormat,
+    /// Rows per fetch when the caller doesn't ask for a count.
+    pub page_size: i64,
+    last_used: Instant,
+}
+
+impl Cursor {
+    /// Begin a transaction on `conn` and declare a cursor for `sql`.
+    ///
+    /// Returns the prepared statement too, for its column descriptions.
+    pub async fn open(
+        conn: Object,
+        sql: &str,
+        params: &[Value],
+        row_format: RowFormat,
+        page_size: i64,
+    ) -> Result<(Self, Statement)> {
+        let stmt = conn.prepare(sql).await.context("Failed to prepare query")?;
+        let bound = bind_params(params, stmt.params())?;
+        let name = format!("fgp_{}", uuid::Uuid::new_v4().simple());
+
+        conn.batch_execute("BEGIN")
+            .await
+            .context("Failed to start cursor transaction")?;
+        let declare = format!(
+            "DECLARE {} NO SCROLL CURSOR FOR {}",
+            name,
+            sql.trim_end().trim_end_matches(';')
+        );
+        let declared = match conn.prepare_typed(&declare, stmt.params()).await {
+            Ok(declare) => conn.execute(&declare, &as_refs(&bound)).await,
+            Err(e) => Err(e),
+        };
+        let mut cursor = Self {
+            conn: Some(conn),
+            name,
+            row_format,
+            page_size,
+            last_used: Instant::now(),
+        };
+        if let Err(e) = declared {
+            cursor.close(false).await;
+            return Err(e)
+                .context("Failed to declare cursor (only SELECT and VALUES can be paged)");
+        }
+        Ok((cursor, stmt))
+    }
+
+    /// The reserved connection, inside the cursor's transaction.
+    pub fn client(&self) -> Result<&Object> {
+        self.conn
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Cursor is closed"))
+    }
+
+    /// Fetch up to `count` more rows.
+    pub async fn fetch(&mut self, count: i64) -> Result<Vec<Row>> {
+        self.last_used = Instant::now();
+        let rows = self
+            .client()?
+            .query(
+                format!("FETCH FORWARD {} FROM {}", count, self.name).as_str(),
+                &[],
+            )
+            .await
+            .context("Fetch failed")?;
+        self.last_used = Instant::now();
+        Ok(rows)
+    }
+
+    /// End the cursor's transaction and hand the connection back to the pool.
+    ///
+    /// A connection that can't be cleanly reset is discarded instead.
+    pub async fn close(&mut self, commit: bool) {
+        let Some(conn) = self.conn.take() else {
+            return;
+        };
+        let end = if commit { "COMMIT" } else { "ROLLBACK" };
+        if let Err(e) = conn.batch_execute(end).await {
+            tracing::warn!("Failed to close cursor {}: {}", self.name, e);
+            let _ = Object::take(conn);
+        }
+    }
+}
+
+/// Open cursors by id.
+#[derive(Default)]
+pub struct Cursors {
+    open: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Cursor>>>>,
+}
+
+impl Cursors {
+    /// Register a cursor and return its id.
+    pub fn insert(&self, cursor: Cursor) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        self.open
+            .lock()
+            .unwrap()
+            .insert(id.clone(), Arc::new(tokio::sync::Mutex::new(cursor)));
+        id
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.open.lock().unwrap().is_empty()
+    }
+
+    pub fn get(&self, id: &str) -> Result<Arc<tokio::sync::Mutex<Cursor>>> {
+        self.open
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Unknown or expired cursor: {}", id))
+    }
+
+    pub fn remove(&self, id: &str) -> Option<Arc<tokio::sync::Mutex<Cursor>>> {
+        self.open.lock().unwrap().remove(id)
+    }
+
+    /// Unregister and return cursors idle for longer than `CURSOR_IDLE_TIMEOUT`.
+    ///
+    /// Cursors in the middle of a fetch are never considered idle.
+    pub fn take_expired(&self) -> Vec<(String, Arc<tokio::sync::Mutex<Cursor>>)> {
+        let mut open = self.open.lock().unwrap();
+        let expired: Vec<String> = open
+            .iter()
+            .filter(|(_, cursor)| {
+                cursor
+                    .try_lock()
+                    .map(|c| c.last_used.elapsed() > CURSOR_IDLE_TIMEOUT)
+                    .unwrap_or(false)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| open.remove(&id).map(|cursor| (id, cursor)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(idle: Duration) -> Cursor {
+        Cursor {
+            conn: None,
+            name: "fgp_test".to_string(),
+            row_format: RowFormat::default(),
+            page_size: 100,
+            last_used: Instant::now() - idle,
+        }
+    }
+
+    #[test]
+    fn expires_idle_cursors() {
+        let cursors = Cursors::default();
+        let idle = cursors.insert(cursor(CURSOR_IDLE_TIMEOUT + Duration::from_secs(1)));
+        let fresh = cursors.insert(cursor(Duration::ZERO));
+        let busy = cursors.insert(cursor(CURSOR_IDLE_TIMEOUT * 2));
+        let fetching = cursors.get(&busy).unwrap();
+        let _fetching = fetching.try_lock().unwrap();
+
+        let expired = cursors.take_expired();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, idle);
+        assert!(cursors.get(&idle).is_err());
+        assert!(cursors.get(&fresh).is_ok());
+        assert!(cursors.get(&busy).is_ok());
+    }
+}
@@ -0,0 +1,627 @@
+//! Conversion of binary column values to JSON.
+
+use byteorder::{BigEndian, ReadBytesExt};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, Utc};
+use fallible_iterator::FallibleIterator;
+use postgres_protocol::types as wire;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fmt::Write;
+use std::net::IpAddr;
+use tokio_postgres::types::{FromSql, Kind, Type};
+
+use crate::types::{type_name, Numeric};
+
+type DecodeResult = Result<Value, Box<dyn Error + Sync + Send>>;
+
+/// Column bytes left undecoded by the driver.
+pub struct RawValue<'a>(pub &'a [u8]);
+
+impl<'a> FromSql<'a> for RawValue<'a> {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        Ok(RawValue(raw))
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+}
+
+/// Decode a non-NULL value of type `ty` from its binary representation.
+///
+/// Numbers that JSON can't hold exactly (numeric, money, NaN/Infinity floats)
+/// become strings, timestamps become RFC 3339 / ISO 8601 strings, intervals
+/// ISO 8601 durations and bytea `\x`-prefixed hex. Arrays nest, ranges become
+/// `{lower, upper, lower_inc, upper_inc, empty}` and composites objects.
+pub fn to_json(ty: &Type, raw: &[u8]) -> DecodeResult {
+    match ty.kind() {
+        Kind::Enum(_) => return text(raw),
+        Kind::Domain(base) => return to_json(base, raw),
+        Kind::Array(member) => return array(member, raw),
+        Kind::Range(subtype) => return range(subtype, raw),
+        Kind::Multirange(subtype) => return multirange(subtype, raw),
+        Kind::Composite(fields) => {
+            let names = fields.iter().map(|f| f.name().to_string());
+            let types: Vec<Type> = fields.iter().map(|f| f.type_().clone()).collect();
+            return record(raw, names, Some(&types));
+        }
+        _ => {}
+    }
+
+    match *ty {
+        Type::BOOL => Ok(Value::Bool(wire::bool_from_sql(raw)?)),
+        Type::INT2 => Ok(json!(wire::int2_from_sql(raw)?)),
+        Type::INT4 => Ok(json!(wire::int4_from_sql(raw)?)),
+        Type::INT8 => Ok(json!(wire::int8_from_sql(raw)?)),
+        Type::OID
+        | Type::XID
+        | Type::CID
+        | Type::REGPROC
+        | Type::REGPROCEDURE
+        | Type::REGOPER
+        | Type::REGOPERATOR
+        | Type::REGCLASS
+        | Type::REGTYPE
+        | Type::REGCONFIG
+        | Type::REGDICTIONARY
+        | Type::REGNAMESPACE
+        | Type::REGROLE
+        | Type::REGCOLLATION => Ok(json!(wire::oid_from_sql(raw)?)),
+        Type::XID8 => {
+            let mut buf = raw;
+            Ok(json!(buf.read_u64::<BigEndian>()?))
+        }
+        Type::FLOAT4 => Ok(float(wire::float4_from_sql(raw)?.to_string())),
+        Type::FLOAT8 => Ok(float(wire::float8_from_sql(raw)?.to_string())),
+        Type::NUMERIC => Ok(json!(Numeric::from_sql(ty, raw)?.to_string())),
+        Type::MONEY => Ok(json!(money(wire::int8_from_sql(raw)?))),
+        Type::CHAR => {
+            let c = wire::char_from_sql(raw)? as u8;
+            Ok(json!(if c == 0 {
+                String::new()
+            } else {
+                (c as char).to_string()
+            }))
+        }
+        Type::TEXT
+        | Type::VARCHAR
+        | Type::BPCHAR
+        | Type::NAME
+        | Type::UNKNOWN
+        | Type::XML
+        | Type::REFCURSOR
+        | Type::PG_NODE_TREE => text(raw),
+        Type::JSON => Ok(serde_json::from_slice(raw)?),
+        Type::JSONB => match raw.split_first() {
+            Some((1, json)) => Ok(serde_json::from_slice(json)?),
+            _ => Err("unsupported jsonb version".into()),
+        },
+        Type::JSONPATH => match raw.split_first() {
+            Some((1, path)) => text(path),
+            _ => Err("unsupported jsonpath version".into()),
+        },
+        Type::BYTEA => Ok(json!(hex(raw))),
+        Type::UUID => Ok(json!(
+            uuid::Uuid::from_bytes(wire::uuid_from_sql(raw)?).to_string()
+        )),
+        Type::DATE => Ok(json!(date(wire::date_from_sql(raw)?)?)),
+        Type::TIME => Ok(json!(time(wire::time_from_sql(raw)?)?)),
+        Type::TIMETZ => {
+            let mut buf = raw;
+            let micros = buf.read_i64::<BigEndian>()?;
+            // Stored as seconds west of UTC.
+            let offset = -buf.read_i32::<BigEndian>()?;
+            Ok(json!(format!("{}{}", time(micros)?, utc_offset(offset))))
+        }
+        Type::TIMESTAMP => Ok(json!(timestamp(wire::timestamp_from_sql(raw)?, false)?)),
+        Type::TIMESTAMPTZ => Ok(json!(timestamp(wire::timestamp_from_sql(raw)?, true)?)),
+        Type::INTERVAL => {
+            let mut buf = raw;
+            let micros = buf.read_i64::<BigEndian>()?;
+            let days = buf.read_i32::<BigEndian>()?;
+            let months = buf.read_i32::<BigEndian>()?;
+            Ok(json!(interval(months, days, micros)))
+        }
+        Type::INET | Type::CIDR => {
+            let inet = wire::inet_from_sql(raw)?;
+            let full = match inet.addr() {
+                IpAddr::V4(_) => 32,
+                IpAddr::V6(_) => 128,
+            };
+            if *ty == Type::INET && inet.netmask() == full {
+                Ok(json!(inet.addr().to_string()))
+            } else {
+                Ok(json!(format!("{}/{}", inet.addr(), inet.netmask())))
+            }
+        }
+        Type::MACADDR | Type::MACADDR8 => Ok(json!(raw
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(":"))),
+        Type::BIT | Type::VARBIT => {
+            let bits = wire::varbit_from_sql(raw)?;
+            let s: String = (0..bits.len())
+                .map(|i| {
+                    if bits.bytes()[i / 8] & (0x80 >> (i % 8)) != 0 {
+                        '1'
+                    } else {
+                        '0'
+                    }
+                })
+                .collect();
+            Ok(json!(s))
+        }
+        Type::PG_LSN => {
+            let lsn = wire::lsn_from_sql(raw)?;
+            Ok(json!(format!("{:X}/{:X}", lsn >> 32, lsn & 0xFFFF_FFFF)))
+        }
+        Type::TID => {
+            let mut buf = raw;
+            let block = buf.read_u32::<BigEndian>()?;
+            let offset = buf.read_u16::<BigEndian>()?;
+            Ok(json!(format!("({},{})", block, offset)))
+        }
+        Type::TXID_SNAPSHOT | Type::PG_SNAPSHOT => {
+            let mut buf = raw;
+            let count = buf.read_i32::<BigEndian>()?;
+            let xmin = buf.read_u64::<BigEndian>()?;
+            let xmax = buf.read_u64::<BigEndian>()?;
+            let xips = (0..count)
+                .map(|_| buf.read_u64::<BigEndian>().map(|x| x.to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(json!(format!("{}:{}:{}", xmin, xmax, xips.join(","))))
+        }
+        Type::POINT => {
+            let p = wire::point_from_sql(raw)?;
+            Ok(json!(point(p.x(), p.y())))
+        }
+        Type::LSEG => {
+            let v = floats(raw, 4)?;
+            Ok(json!(format!(
+                "[{},{}]",
+                point(v[0], v[1]),
+                point(v[2], v[3])
+            )))
+        }
+        Type::BOX => {
+            let b = wire::box_from_sql(raw)?;
+            let (hi, lo) = (b.upper_right(), b.lower_left());
+            Ok(json!(format!(
+                "{},{}",
+                point(hi.x(), hi.y()),
+                point(lo.x(), lo.y())
+            )))
+        }
+        Type::PATH => {
+            let path = wire::path_from_sql(raw)?;
+            let closed = path.closed();
+            let points = points(path.points())?;
+            Ok(json!(if closed {
+                format!("({})", points)
+            } else {
+                format!("[{}]", points)
+            }))
+        }
+        Type::POLYGON => {
+            let mut buf = raw;
+            let count = buf.read_i32::<BigEndian>()?;
+            let coords = floats(buf, count.max(0) as usize * 2)?;
+            let points: Vec<String> = coords.chunks(2).map(|c| point(c[0], c[1])).collect();
+            Ok(json!(format!("({})", points.join(","))))
+        }
+        Type::LINE => {
+            let v = floats(raw, 3)?;
+            Ok(json!(format!("{{{},{},{}}}", v[0], v[1], v[2])))
+        }
+        Type::CIRCLE => {
+            let v = floats(raw, 3)?;
+            Ok(json!(format!("<{},{}>", point(v[0], v[1]), v[2])))
+        }
+        Type::TS_VECTOR => Ok(json!(tsvector(raw)?)),
+        Type::TSQUERY => Ok(json!(tsquery(raw)?)),
+        Type::VOID => Ok(Value::Null),
+        // Anonymous rows carry their field types but not their names.
+        Type::RECORD => record(raw, (1..).map(|i| format!("f{}", i)), None),
+        _ => match ty.name() {
+            "citext" => text(raw),
+            "ltree" | "lquery" | "ltxtquery" => match raw.split_first() {
+                Some((1, label)) => text(label),
+                _ => Err(format!("unsupported {} version", ty.name()).into()),
+            },
+            "hstore" => {
+                let mut map = serde_json::Map::new();
+                let mut entries = wire::hstore_from_sql(raw)?;
+                while let Some((key, value)) = entries.next()? {
+                    map.insert(
+                        key.to_string(),
+                        value.map(|v| json!(v)).unwrap_or(Value::Null),
+                    );
+                }
+                Ok(Value::Object(map))
+            }
+            _ => Err(format!(
+                "type {} cannot be converted to JSON; cast it in the query (e.g. col::text)",
+                type_name(ty)
+            )
+            .into()),
+        },
+    }
+}
+
+fn nullable(ty: &Type, raw: Option<&[u8]>) -> DecodeResult {
+    raw.map_or(Ok(Value::Null), |raw| to_json(ty, raw))
+}
+
+/// Nested JSON arrays, one level per dimension, in row-major order.
+fn array(member: &Type, raw: &[u8]) -> DecodeResult {
+    let array = wire::array_from_sql(raw)?;
+    let dimensions: Vec<usize> = array
+        .dimensions()
+        .map(|d| Ok(d.len.max(0) as usize))
+        .collect()?;
+    let mut values: Vec<Value> = array.values().map(|v| nullable(member, v)).collect()?;
+
+    if dimensions.is_empty() {
+        return Ok(Value::Array(Vec::new()));
+    }
+    // Fold the innermost dimension into arrays, then the next one out, and so on.
+    for &len in dimensions[1..].iter().rev() {
+        values = values
+            .chunks(len.max(1))
+            .map(|chunk| Value::Array(chunk.to_vec()))
+            .collect();
+    }
+    Ok(Value::Array(values))
+}
+
+fn range(subtype: &Type, raw: &[u8]) -> DecodeResult {
+    let bound = |b: wire::RangeBound<Option<&[u8]>>| -> Result<(Value, bool), Box<dyn Error + Sync + Send>> {
+        match b {
+            wire::RangeBound::Inclusive(v) => Ok((nullable(subtype, v)?, true)),
+            wire::RangeBound::Exclusive(v) => Ok((nullable(subtype, v)?, false)),
+            wire::RangeBound::Unbounded => Ok((Value::Null, false)),
+        }
+    };
+
+    match wire::range_from_sql(raw)? {
+        wire::Range::Empty => Ok(json!({
+            "lower": null,
+            "upper": null,
+            "lower_inc": false,
+            "upper_inc": false,
+            "empty": true,
+        })),
+        wire::Range::Nonempty(lower, upper) => {
+            let (lower, lower_inc) = bound(lower)?;
+            let (upper, upper_inc) = bound(upper)?;
+            Ok(json!({
+                "lower": lower,
+                "upper": upper,
+                "lower_inc": lower_inc,
+                "upper_inc": upper_inc,
+                "empty": false,
+            }))
+        }
+    }
+}
+
+fn multirange(subtype: &Type, mut raw: &[u8]) -> DecodeResult {
+    let count = raw.read_i32::<BigEndian>()?;
+    let mut ranges = Vec::with_capacity(count.max(0) as usize);
+    for _ in 0..count {
+        let len = usize::try_from(raw.read_i32::<BigEndian>()?)?;
+        let (bytes, rest) = raw.split_at_checked(len).ok_or("truncated multirange")?;
+        ranges.push(range(subtype, bytes)?);
+        raw = rest;
+    }
+    Ok(Value::Array(ranges))
+}
+
+/// Composite and record values: a field count, then `(oid, length, bytes)` per field.
+///
+/// Field types come from `types` when known, otherwise from the OIDs on the wire.
+fn record(
+    mut raw: &[u8],
+    names: impl Iterator<Item = String>,
+    types: Option<&[Type]>,
+) -> DecodeResult {
+    let count = raw.read_i32::<BigEndian>()?;
+    let mut map = serde_json::Map::new();
+    for (i, name) in names.take(count.max(0) as usize).enumerate() {
+        let oid = raw.read_u32::<BigEndian>()?;
+        let len = raw.read_i32::<BigEndian>()?;
+        let value = if len < 0 {
+            None
+        } else {
+            let (bytes, rest) = raw
+                .split_at_checked(len as usize)
+                .ok_or("truncated record")?;
+            raw = rest;
+            Some(bytes)
+        };
+        let ty = match types.and_then(|t| t.get(i)) {
+            Some(ty) => ty.clone(),
+            None => Type::from_oid(oid)
+                .ok_or_else(|| format!("field {} has unknown type oid {}", name, oid))?,
+        };
+        map.insert(name, nullable(&ty, value)?);
+    }
+    Ok(Value::Object(map))
+}
+
+fn text(raw: &[u8]) -> DecodeResult {
+    Ok(json!(wire::text_from_sql(raw)?))
+}
+
+/// Finite floats as JSON numbers, NaN and infinities as strings.
+fn float(shortest: String) -> Value {
+    match shortest.as_str() {
+        "NaN" => json!("NaN"),
+        "inf" => json!("Infinity"),
+        "-inf" => json!("-Infinity"),
+        // Going through the shortest text keeps float4 values like 0.1 exact.
+        s => s.parse::<f64>().map(|f| json!(f)).unwrap_or(Value::Null),
+    }
+}
+
+fn money(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    let abs = cents.unsigned_abs();
+    format!("{}{}.{:02}", sign, abs / 100, abs % 100)
+}
+
+fn hex(raw: &[u8]) -> String {
+    let mut s = String::with_capacity(2 + raw.len() * 2);
+    s.push_str("\\x");
+    for b in raw {
+        let _ = write!(s, "{:02x}", b);
+    }
+    s
+}
+
+fn pg_epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2000, 1, 1)
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .expect("valid epoch")
+}
+
+fn date(days: i32) -> Result<String, Box<dyn Error + Sync + Send>> {
+    match days {
+        i32::MAX => Ok("infinity".into()),
+        i32::MIN => Ok("-infinity".into()),
+        _ => pg_epoch()
+            .date()
+            .checked_add_signed(Duration::days(days as i64))
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .ok_or_else(|| "date out of range".into()),
+    }
+}
+
+fn time(micros: i64) -> Result<String, Box<dyn Error + Sync + Send>> {
+    if micros == 86_400_000_000 {
+        return Ok("24:00:00".into());
+    }
+    let secs = u32::try_from(micros.div_euclid(1_000_000))?;
+    let nanos = (micros.rem_euclid(1_000_000) * 1000) as u32;
+    NaiveTime::from_num_seconds_from_midnight_opt(secs, nanos)
+        .map(|t| t.format("%H:%M:%S%.f").to_string())
+        .ok_or_else(|| "time out of range".into())
+}
+
+fn timestamp(micros: i64, with_tz: bool) -> Result<String, Box<dyn Error + Sync + Send>> {
+    match micros {
+        i64::MAX => return Ok("infinity".into()),
+        i64::MIN => return Ok("-infinity".into()),
+        _ => {}
+    }
+    let ts = pg_epoch()
+        .checked_add_signed(Duration::microseconds(micros))
+        .ok_or("timestamp out of range")?;
+    if with_tz {
+        Ok(DateTime::<Utc>::from_naive_utc_and_offset(ts, Utc)
+            .to_rfc3339_opts(SecondsFormat::AutoSi, false))
+    } else {
+        Ok(ts.format("%Y-%m-%dT%H:%M:%S%.f").to_string())
+    }
+}
+
+fn utc_offset(seconds_east: i32) -> String {
+    let sign = if seconds_east < 0 { '-' } else { '+' };
+    let abs = seconds_east.unsigned_abs();
+    let (h, m, s) = (abs / 3600, abs / 60 % 60, abs % 60);
+    if s == 0 {
+        format!("{}{:02}:{:02}", sign, h, m)
+    } else {
+        format!("{}{:02}:{:02}:{:02}", sign, h, m, s)
+    }
+}
+
+/// ISO 8601 duration, as Postgres prints with `IntervalStyle = iso_8601`.
+fn interval(months: i32, days: i32, micros: i64) -> String {
+    if months == 0 && days == 0 && micros == 0 {
+        return "PT0S".into();
+    }
+
+    let mut s = String::from("P");
+    let (years, months) = (months / 12, months % 12);
+    for (value, unit) in [
+        (years as i64, 'Y'),
+        (months as i64, 'M'),
+        (days as i64, 'D'),
+    ] {
+        if value != 0 {
+            let _ = write!(s, "{}{}", value, unit);
+        }
+    }
+
+    if micros != 0 {
+        s.push('T');
+        let hours = micros / 3_600_000_000;
+        let minutes = micros % 3_600_000_000 / 60_000_000;
+        let seconds = micros % 60_000_000;
+        if hours != 0 {
+            let _ = write!(s, "{}H", hours);
+        }
+        if minutes != 0 {
+            let _ = write!(s, "{}M", minutes);
+        }
+        if seconds != 0 {
+            let sign = if seconds < 0 { "-" } else { "" };
+            let abs = seconds.unsigned_abs();
+            let _ = write!(s, "{}{}", sign, abs / 1_000_000);
+            let fraction = abs % 1_000_000;
+            if fraction != 0 {
+                let digits = format!("{:06}", fraction);
+                let _ = write!(s, ".{}", digits.trim_end_matches('0'));
+            }
+            s.push('S');
+        }
+    }
+    s
+}
+
+fn point(x: f64, y: f64) -> String {
+    format!("({},{})", x, y)
+}
+
+fn points(
+    mut iter: impl FallibleIterator<Item = wire::Point, Error = Box<dyn Error + Sync + Send>>,
+) -> Result<String, Box<dyn Error + Sync + Send>> {
+    let mut out = Vec::new();
+    while let Some(p) = iter.next()? {
+        out.push(point(p.x(), p.y()));
+    }
+    Ok(out.join(","))
+}
+
+fn floats(mut raw: &[u8], count: usize) -> Result<Vec<f64>, Box<dyn Error + Sync + Send>> {
+    let values = (0..count)
+        .map(|_| raw.read_f64::<BigEndian>())
+        .collect::<Result<Vec<_>, _>>()?;
+    if !raw.is_empty() {
+        return Err("invalid buffer size".into());
+    }
+    Ok(values)
+}
+
+fn read_cstr<'a>(buf: &mut &'a [u8]) -> Result<&'a str, Box<dyn Error + Sync + Send>> {
+    let end = buf
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or("unterminated string")?;
+    let s = std::str::from_utf8(&buf[..end])?;
+    *buf = &buf[end + 1..];
+    Ok(s)
+}
+
+fn quote_lexeme(lexeme: &str) -> String {
+    format!("'{}'", lexeme.replace('\\', "\\\\").replace('\'', "''"))
+}
+
+/// Text form of a tsvector, e.g. `'cat':3 'fat':2A`.
+fn tsvector(mut raw: &[u8]) -> Result<String, Box<dyn Error + Sync + Send>> {
+    let count = raw.read_i32::<BigEndian>()?;
+    let mut lexemes = Vec::with_capacity(count.max(0) as usize);
+    for _ in 0..count {
+        let mut entry = quote_lexeme(read_cstr(&mut raw)?);
+        let positions = raw.read_u16::<BigEndian>()?;
+        for i in 0..positions {
+            let pos = raw.read_u16::<BigEndian>()?;
+            entry.push(if i == 0 { ':' } else { ',' });
+            let _ = write!(entry, "{}", pos & 0x3FFF);
+            match pos >> 14 {
+                3 => entry.push('A'),
+                2 => entry.push('B'),
+                1 => entry.push('C'),
+                _ => {}
+            }
+        }
+        lexemes.push(entry);
+    }
+    Ok(lexemes.join(" "))
+}
+
+enum TsItem {
+    Operand {
+        weight: u8,
+        prefix: bool,
+        lexeme: String,
+    },
+    Not,
+    And,
+    Or,
+    Phrase(i16),
+}
+
+/// Text form of a tsquery. Items arrive in prefix order, right operand first.
+fn tsquery(mut raw: &[u8]) -> Result<String, Box<dyn Error + Sync + Send>> {
+    let count = raw.read_i32::<BigEndian>()?;
+    let mut items = Vec::with_capacity(count.max(0) as usize);
+    for _ in 0..count {
+        let item = match raw.read_u8()? {
+            1 => TsItem::Operand {
+                weight: raw.read_u8()?,
+                prefix: raw.read_u8()? != 0,
+                lexeme: read_cstr(&mut raw)?.to_string(),
+            },
+            2 => match raw.read_u8()? {
+                1 => TsItem::Not,
+                2 => TsItem::And,
+                3 => TsItem::Or,
+                4 => TsItem::Phrase(raw.read_i16::<BigEndian>()?),
+                op => return Err(format!("unknown tsquery operator {}", op).into()),
+            },
+            t => return Err(format!("unknown tsquery item type {}", t).into()),
+        };
+        items.push(item);
+    }
+
+    fn render(
+        items: &[TsItem],
+        pos: &mut usize,
+        nested: bool,
+    ) -> Result<String, Box<dyn Error + Sync + Send>> {
+        let item = items.get(*pos).ok_or("truncated tsquery")?;
+        *pos += 1;
+        let op = match item {
+            TsItem::Operand {
+                weight,
+                prefix,
+                lexeme,
+            } => {
+                let mut s = quote_lexeme(lexeme);
+                if *prefix || *weight != 0 {
+                    s.push(':');
+                    if *prefix {
+                        s.push('*');
+                    }
+                    for (bit, c) in [(8, 'A'), (4, 'B'), (2, 'C'), (1, 'D')] {
+                        if weight & bit != 0 {
+                            s.push(c);
+                        }
+                    }
+                }
+                return Ok(s);
+            }
+            TsItem::Not => return Ok(format!("!{}", render(items, pos, true)?)),
+            TsItem::And => "&".to_string(),
+            TsItem::Or => "|".to_string(),
+            TsItem::Phrase(1) => "<->".to_string(),
+            TsItem::Phrase(d) => format!("<{}>", d),
+        };
+        let right = render(items, pos, true)?;
+        let left = render(items, pos, true)?;
+        let expr = format!("{} {} {}", left, op, right);
+        Ok(if nested {
+            format!("( {} )", expr)
+        } else {
+            expr
+        })
+    }
+
+    if items.is_empty() {
+        return Ok(String::new());
+    }
+    render(&items, &mut 0, false)
+}
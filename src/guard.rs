@@ -0,0 +1,114 @@
+//! Confirmation for destructive statements on guarded connections.
+//!
+//! A guarded request is refused once with a token; resending exactly the same
+//! statements with that token runs them.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::classify::DestructiveKind;
+use crate::client::TxStatement;
+
+/// How long a confirmation token can be redeemed.
+pub const CONFIRMATION_TTL: Duration = Duration::from_secs(300);
+
+/// Destructive statement kinds that need confirmation on a connection.
+///
+/// In connections.json, `"guard": true` covers every kind and a list picks some.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "GuardSetting", into = "Vec<DestructiveKind>")]
+pub struct GuardConfig(Vec<DestructiveKind>);
+
+#[derive(Deserialize)]
+#[serde(
+    untagged,
+    expecting = "true, false or a list of destructive statement kinds"
+)]
+enum GuardSetting {
+    All(bool),
+    Only(Vec<DestructiveKind>),
+}
+
+impl From<GuardSetting> for GuardConfig {
+    fn from(setting: GuardSetting) -> Self {
+        match setting {
+            GuardSetting::All(true) => Self(DestructiveKind::ALL.to_vec()),
+            GuardSetting::All(false) => Self::default(),
+            GuardSetting::Only(kinds) => Self(kinds),
+        }
+    }
+}
+
+impl From<GuardConfig> for Vec<DestructiveKind> {
+    fn from(config: GuardConfig) -> Self {
+        config.0
+    }
+}
+
+impl GuardConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.0.is_empty()
+    }
+
+    pub fn guards(&self, kind: DestructiveKind) -> bool {
+        self.0.contains(&kind)
+    }
+}
+
+/// Outstanding confirmation tokens, each bound to the statements it was issued for.
+#[derive(Default)]
+pub struct Confirmations {
+    issued: Mutex<HashMap<String, (u64, Instant)>>,
+}
+
+impl Confirmations {
+    /// Issue a token for running `statements` once.
+    pub fn issue(&self, statements: &[TxStatement]) -> String {
+        let mut issued = self.issued.lock().unwrap();
+        issued.retain(|_, (_, at)| at.elapsed() < CONFIRMATION_TTL);
+        let token = uuid::Uuid::new_v4().to_string();
+        issued.insert(token.clone(), (fingerprint(statements), Instant::now()));
+        token
+    }
+
+    /// Use up `token`, which must have been issued for these exact statements.
+    pub fn redeem(&self, token: &str, statements: &[TxStatement]) -> Result<()> {
+        let mut issued = self.issued.lock().unwrap();
+        match issued.get(token) {
+            Some((_, at)) if at.elapsed() >= CONFIRMATION_TTL => {
+                issued.remove(token);
+                anyhow::bail!(
+                    "Confirmation token has expired; resend without confirm for a new one"
+                )
+            }
+            Some((hash, _)) if *hash == fingerprint(statements) => {
+                issued.remove(token);
+                Ok(())
+            }
+            // A mismatch leaves the token for the request it was issued for
+            Some(_) => anyhow::bail!(
+                "Confirmation token was issued for different statements or parameters"
+            ),
+            None => anyhow::bail!(
+                "Unknown or already used confirmation token; resend without confirm for a new one"
+            ),
+        }
+    }
+}
+
+fn fingerprint(statements: &[TxStatement]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for statement in statements {
+        statement.sql.hash(&mut hasher);
+        statement.params.len().hash(&mut hasher);
+        for param in &statement.params {
+            param.to_string().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
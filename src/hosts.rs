@@ -0,0 +1,557 @@
+//! Connections naming several hosts, and choosing one by `target_session_attrs`.
+
+use anyhow::{Context, Result};
+use deadpool_postgres::Connect;
+use futures_util::future::BoxFuture;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tokio_postgres::tls::{MakeTlsConnect, TlsConnect};
+use tokio_postgres::{Client, Config, Error, Socket};
+
+/// Which servers a connection may use, named as in libpq.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TargetSessionAttrs {
+    #[default]
+    Any,
+    /// Sessions accept writes by default.
+    ReadWrite,
+    /// Sessions are read-only by default.
+    ReadOnly,
+    /// The server isn't in recovery.
+    Primary,
+    /// The server is a hot standby.
+    Standby,
+    /// A standby if one is up, otherwise any server.
+    PreferStandby,
+}
+
+impl TargetSessionAttrs {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TargetSessionAttrs::Any => "any",
+            TargetSessionAttrs::ReadWrite => "read-write",
+            TargetSessionAttrs::ReadOnly => "read-only",
+            TargetSessionAttrs::Primary => "primary",
+            TargetSessionAttrs::Standby => "standby",
+            TargetSessionAttrs::PreferStandby => "prefer-standby",
+        }
+    }
+
+    /// Whether a server in state `state` will do; prefer-standby asks for a standby first.
+    fn accepts(&self, state: ServerState) -> bool {
+        match self {
+            TargetSessionAttrs::Any => true,
+            TargetSessionAttrs::ReadWrite => !state.read_only,
+            TargetSessionAttrs::ReadOnly => state.read_only,
+            TargetSessionAttrs::Primary => !state.in_recovery,
+            TargetSessionAttrs::Standby | TargetSessionAttrs::PreferStandby => state.in_recovery,
+        }
+    }
+}
+
+impl FromStr for TargetSessionAttrs {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "any" => Ok(TargetSessionAttrs::Any),
+            "read-write" => Ok(TargetSessionAttrs::ReadWrite),
+            "read-only" => Ok(TargetSessionAttrs::ReadOnly),
+            "primary" => Ok(TargetSessionAttrs::Primary),
+            "standby" => Ok(TargetSessionAttrs::Standby),
+            "prefer-standby" => Ok(TargetSessionAttrs::PreferStandby),
+            _ => anyhow::bail!(
+                "expected any, read-write, read-only, primary, standby or prefer-standby"
+            ),
+        }
+    }
+}
+
+/// In which order hosts are tried, named as in libpq.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadBalanceHosts {
+    /// In the order given.
+    #[default]
+    Disable,
+    /// In random order, spreading connections over the hosts.
+    Random,
+}
+
+impl FromStr for LoadBalanceHosts {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "disable" => Ok(LoadBalanceHosts::Disable),
+            "random" => Ok(LoadBalanceHosts::Random),
+            _ => anyhow::bail!("expected disable or random"),
+        }
+    }
+}
+
+/// One of a connection's hosts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostPort {
+    pub host: String,
+    pub port: u16,
+}
+
+impl HostPort {
+    /// Whether `host` is the directory of the server's Unix socket, as in libpq.
+    pub fn is_socket(&self) -> bool {
+        self.host.starts_with('/')
+    }
+}
+
+impl fmt::Display for HostPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_socket() {
+            write!(
+                f,
+                "{}/.s.PGSQL.{}",
+                self.host.trim_end_matches('/'),
+                self.port
+            )
+        } else if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Parse a comma-separated list of `host[:port]`, e.g. `db1,db2:5433,[::1]:5434`.
+///
+/// Hosts without a port use `default_port`; empty entries mean `localhost`.
+pub fn parse_hosts(hosts: &str, default_port: u16) -> Result<Vec<HostPort>> {
+    hosts
+        .split(',')
+        .map(|entry| {
+            let entry = entry.trim();
+            let (host, port) = if let Some(rest) = entry.strip_prefix('[') {
+                let (host, rest) = rest
+                    .split_once(']')
+                    .with_context(|| format!("Missing ']' in host '{}'", entry))?;
+                match rest.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None if rest.is_empty() => (host, None),
+                    None => anyhow::bail!("Invalid host '{}'", entry),
+                }
+            } else {
+                match entry.rsplit_once(':') {
+                    // A bare IPv6 address
+                    Some((host, _)) if host.contains(':') => (entry, None),
+                    Some((host, port)) => (host, Some(port)),
+                    None => (entry, None),
+                }
+            };
+            let port = match port {
+                Some(port) => port
+                    .parse()
+                    .with_context(|| format!("Invalid port in host '{}'", entry))?,
+                None => default_port,
+            };
+            Ok(HostPort {
+                host: if host.is_empty() { "localhost" } else { host }.to_string(),
+                port,
+            })
+        })
+        .collect()
+}
+
+/// Combine libpq's separate lists of hosts and ports, e.g. `db1,db2` and `5432,5433`,
+/// into a `host` and `port` for `parse_hosts`.
+///
+/// A single port applies to every host.
+pub fn with_ports(hosts: &str, ports: &str) -> Result<(String, u16)> {
+    let parse_port = |port: &str| -> Result<u16> {
+        if port.is_empty() {
+            return Ok(5432);
+        }
+        port.parse()
+            .with_context(|| format!("Invalid port '{}'", port))
+    };
+    if !ports.contains(',') {
+        return Ok((hosts.to_string(), parse_port(ports.trim())?));
+    }
+
+    let hosts: Vec<&str> = hosts.split(',').map(str::trim).collect();
+    let ports: Vec<&str> = ports.split(',').map(str::trim).collect();
+    if hosts.len() != ports.len() {
+        anyhow::bail!("{} ports given for {} hosts", ports.len(), hosts.len());
+    }
+    let hosts = hosts
+        .iter()
+        .zip(ports)
+        .map(|(host, port)| {
+            let port = parse_port(port)?;
+            // A bare IPv6 address needs brackets before a port
+            Ok(if host.contains(':') && !host.starts_with('[') {
+                format!("[{}]:{}", host, port)
+            } else {
+                format!("{}:{}", host, port)
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((hosts.join(","), 5432))
+}
+
+/// Escape all but unreserved characters as `%XX`, for a part of a URL.
+pub fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Decode `%XX` escapes, e.g. in `postgres://%2Fvar%2Frun%2Fpostgresql/app`.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Split the host list out of a URL such as `postgres://u@db1,db2:5433/app`,
+/// which URL parsers reject.
+///
+/// Returns the URL with its hosts replaced by `localhost` and the list, or `None`
+/// when it names a single host. An empty host, as in `postgres://app@/app?host=/tmp`,
+/// is replaced too, and returned as an empty list.
+pub fn split_url_hosts(url: &str) -> (String, Option<String>) {
+    let Some(start) = url.find("://").map(|i| i + 3) else {
+        return (url.to_string(), None);
+    };
+    let end = url[start..]
+        .find(['/', '?', '#'])
+        .map_or(url.len(), |i| start + i);
+    let start = url[start..end].rfind('@').map_or(start, |i| start + i + 1);
+    let hosts = &url[start..end];
+    if !hosts.is_empty() && !hosts.contains(',') {
+        return (url.to_string(), None);
+    }
+    let single = format!("{}localhost{}", &url[..start], &url[end..]);
+    (single, Some(hosts.to_string()))
+}
+
+/// What a server reports about itself when a connection has to choose.
+#[derive(Debug, Clone, Copy)]
+struct ServerState {
+    in_recovery: bool,
+    read_only: bool,
+}
+
+/// Opens each pool connection on the first host that `target_session_attrs` allows.
+pub struct HostConnector<T> {
+    /// Each host with the settings to connect to it.
+    hosts: Vec<(HostPort, Config)>,
+    attrs: TargetSessionAttrs,
+    load_balance: LoadBalanceHosts,
+    /// The connection forces every session read-only, so only recovery makes a
+    /// server read-only for it.
+    forced_read_only: bool,
+    tls: T,
+    /// The host last connected to.
+    current: Arc<Mutex<HostPort>>,
+    rejected: LastRejection,
+}
+
+impl<T> HostConnector<T> {
+    /// `hosts` must not be empty.
+    pub fn new(
+        hosts: Vec<(HostPort, Config)>,
+        attrs: TargetSessionAttrs,
+        load_balance: LoadBalanceHosts,
+        forced_read_only: bool,
+        tls: T,
+    ) -> Self {
+        let current = Arc::new(Mutex::new(hosts[0].0.clone()));
+        Self {
+            hosts,
+            attrs,
+            load_balance,
+            forced_read_only,
+            tls,
+            current,
+            rejected: Arc::default(),
+        }
+    }
+
+    /// The host last connected to, shared with the connector.
+    pub fn current(&self) -> Arc<Mutex<HostPort>> {
+        self.current.clone()
+    }
+
+    /// Why the last failed connection found no server, shared with the connector.
+    pub fn rejected(&self) -> LastRejection {
+        self.rejected.clone()
+    }
+
+    /// The first host's settings, which the pool keeps but the connector doesn't need.
+    pub fn first_config(&self) -> Config {
+        self.hosts[0].1.clone()
+    }
+
+    /// Hosts in the order to try them.
+    fn ordered(&self) -> Vec<&(HostPort, Config)> {
+        let mut hosts: Vec<_> = self.hosts.iter().collect();
+        if self.load_balance == LoadBalanceHosts::Random {
+            hosts.shuffle(&mut rand::rng());
+        }
+        hosts
+    }
+
+    async fn state(&self, client: &Client) -> Result<ServerState, Error> {
+        let row = client
+            .query_one(
+                "SELECT pg_is_in_recovery(), current_setting('transaction_read_only') = 'on'",
+                &[],
+            )
+            .await?;
+        let in_recovery = row.try_get(0)?;
+        Ok(ServerState {
+            in_recovery,
+            read_only: if self.forced_read_only {
+                in_recovery
+            } else {
+                row.try_get(1)?
+            },
+        })
+    }
+
+    fn connected(&self, host: &HostPort) {
+        let mut current = self.current.lock().unwrap();
+        if *current != *host {
+            tracing::info!("Connected to {} (was {})", host, current);
+            *current = host.clone();
+        }
+    }
+}
+
+impl<T> Connect for HostConnector<T>
+where
+    T: MakeTlsConnect<Socket> + Clone + Sync + Send + 'static,
+    T::Stream: Sync + Send,
+    T::TlsConnect: Sync + Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+{
+    fn connect(&self, _: &Config) -> BoxFuture<'_, Result<(Client, JoinHandle<()>), Error>> {
+        Box::pin(async move {
+            let mut last_error = None;
+            let mut rejected = None;
+            // For prefer-standby: the first server up, in case none is a standby
+            let mut fallback = None;
+            for (host, config) in self.ordered() {
+                let (client, connection) = match config.connect(self.tls.clone()).await {
+                    Ok(connected) => connected,
+                    Err(e) => {
+                        tracing::debug!("Can't connect to {}: {}", host, e);
+                        last_error = Some(e);
+                        continue;
+                    }
+                };
+                let task = tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        tracing::warn!("Connection error: {}", e);
+                    }
+                });
+                if self.attrs == TargetSessionAttrs::Any {
+                    self.connected(host);
+                    return Ok((client, task));
+                }
+
+                let state = match self.state(&client).await {
+                    Ok(state) => state,
+                    Err(e) => {
+                        last_error = Some(e);
+                        continue;
+                    }
+                };
+                if self.attrs.accepts(state) {
+                    self.connected(host);
+                    return Ok((client, task));
+                }
+                let role = if state.in_recovery {
+                    "standby"
+                } else {
+                    "primary"
+                };
+                tracing::debug!(
+                    "Skipping {} ({}) for target_session_attrs={}",
+                    host,
+                    role,
+                    self.attrs.as_str()
+                );
+                if self.attrs == TargetSessionAttrs::PreferStandby && fallback.is_none() {
+                    fallback = Some((host, client, task));
+                    continue;
+                }
+                last_error = Some(closed(client, task).await);
+                rejected = Some(Rejected {
+                    host: host.clone(),
+                    role,
+                    attrs: self.attrs,
+                });
+            }
+
+            if let Some((host, client, task)) = fallback {
+                self.connected(host);
+                return Ok((client, task));
+            }
+            *self.rejected.lock().unwrap() = rejected;
+            Err(last_error.expect("a connection has at least one host"))
+        })
+    }
+}
+
+/// A server that target_session_attrs doesn't allow.
+#[derive(Debug, Clone)]
+pub struct Rejected {
+    host: HostPort,
+    role: &'static str,
+    attrs: TargetSessionAttrs,
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}) doesn't match target_session_attrs={}",
+            self.host,
+            self.role,
+            self.attrs.as_str()
+        )
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Why the last failed connection found no server, when it reached one.
+pub type LastRejection = Arc<Mutex<Option<Rejected>>>;
+
+/// The error for using `client` once its connection is closed.
+///
+/// The driver's errors can't be built outside it, so a rejected server's connection
+/// fails with this one, and the connector's `rejected` says why.
+async fn closed(client: Client, task: JoinHandle<()>) -> Error {
+    task.abort();
+    let _ = task.await;
+    client
+        .batch_execute("")
+        .await
+        .expect_err("the connection is closed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(host: &str, port: u16) -> HostPort {
+        HostPort {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn parses_hosts_and_ports() {
+        let hosts = parse_hosts("db1, db2:5433,/tmp", 5432).unwrap();
+        assert_eq!(
+            hosts,
+            [host("db1", 5432), host("db2", 5433), host("/tmp", 5432)]
+        );
+        assert_eq!(hosts[2].to_string(), "/tmp/.s.PGSQL.5432");
+        assert!(parse_hosts("db1:port", 5432).is_err());
+    }
+
+    #[test]
+    fn parses_ipv6_hosts() {
+        let hosts = parse_hosts("[::1]:5434,[fe80::1],::1", 5432).unwrap();
+        assert_eq!(
+            hosts,
+            [host("::1", 5434), host("fe80::1", 5432), host("::1", 5432)]
+        );
+        assert_eq!(hosts[0].to_string(), "[::1]:5434");
+        assert!(parse_hosts("[::1:5434", 5432).is_err());
+        assert!(parse_hosts("[::1]5434", 5432).is_err());
+    }
+
+    #[test]
+    fn empty_hosts_are_localhost() {
+        assert_eq!(parse_hosts("", 5432).unwrap(), [host("localhost", 5432)]);
+        assert_eq!(
+            parse_hosts("db1,", 5433).unwrap(),
+            [host("db1", 5433), host("localhost", 5433)]
+        );
+    }
+
+    #[test]
+    fn combines_hosts_with_ports() {
+        assert_eq!(
+            with_ports("db1,db2", "5433").unwrap(),
+            ("db1,db2".to_string(), 5433)
+        );
+        assert_eq!(with_ports("db1", "").unwrap(), ("db1".to_string(), 5432));
+        assert_eq!(
+            with_ports("db1,::1,[::2]", "5433,5434,").unwrap(),
+            ("db1:5433,[::1]:5434,[::2]:5432".to_string(), 5432)
+        );
+        let mismatch = with_ports("db1,db2", "5432,5433,5434").unwrap_err();
+        assert_eq!(mismatch.to_string(), "3 ports given for 2 hosts");
+        assert!(with_ports("db1,db2", "5432,x").is_err());
+    }
+
+    #[test]
+    fn splits_url_hosts() {
+        assert_eq!(
+            split_url_hosts("postgres://u:p@db1,db2:5433/app?sslmode=require"),
+            (
+                "postgres://u:p@localhost/app?sslmode=require".to_string(),
+                Some("db1,db2:5433".to_string())
+            )
+        );
+        assert_eq!(
+            split_url_hosts("postgres://app@/app?host=/tmp"),
+            (
+                "postgres://app@localhost/app?host=/tmp".to_string(),
+                Some(String::new())
+            )
+        );
+        assert_eq!(
+            split_url_hosts("postgresql://[::1]:5433,db2"),
+            (
+                "postgresql://localhost".to_string(),
+                Some("[::1]:5433,db2".to_string())
+            )
+        );
+        let single = "postgres://u@db1:5433/app";
+        assert_eq!(split_url_hosts(single), (single.to_string(), None));
+        assert_eq!(split_url_hosts("app"), ("app".to_string(), None));
+    }
+}
@@ -0,0 +1,384 @@
+//! libpq's environment variables, password file and connection service file.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use crate::hosts::{self, HostPort};
+
+/// Connection parameters by libpq keyword, e.g. `host` or `sslmode`, each with
+/// where it came from.
+#[derive(Debug, Clone, Default)]
+pub struct Params(BTreeMap<String, (String, String)>);
+
+impl Params {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|(value, _)| value.as_str())
+    }
+
+    pub fn set(&mut self, key: &str, value: impl Into<String>, origin: &str) {
+        self.0
+            .insert(key.to_string(), (value.into(), origin.to_string()));
+    }
+
+    /// Set `key` unless it is set already.
+    pub fn set_default(&mut self, key: &str, value: &str) {
+        self.0
+            .entry(key.to_string())
+            .or_insert_with(|| (value.to_string(), "default".to_string()));
+    }
+
+    /// Take every parameter `other` has.
+    pub fn merge(&mut self, other: Params) {
+        self.0.extend(other.0);
+    }
+
+    /// Take the parameters of `other` that aren't set yet.
+    pub fn fill(&mut self, other: Params) {
+        for (key, param) in other.0 {
+            self.0.entry(key).or_insert(param);
+        }
+    }
+
+    /// Where each parameter came from, by keyword.
+    pub fn origins(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(key, (_, origin))| (key.as_str(), origin.as_str()))
+    }
+}
+
+/// Environment variables and the parameter each sets.
+const ENV_VARS: &[(&str, &str)] = &[
+    ("PGHOST", "host"),
+    ("PGPORT", "port"),
+    ("PGDATABASE", "dbname"),
+    ("PGUSER", "user"),
+    ("PGPASSWORD", "password"),
+    ("PGSERVICE", "service"),
+    ("PGSSLMODE", "sslmode"),
+    ("PGSSLROOTCERT", "sslrootcert"),
+    ("PGSSLCERT", "sslcert"),
+    ("PGSSLKEY", "sslkey"),
+    ("PGCONNECT_TIMEOUT", "connect_timeout"),
+    ("PGAPPNAME", "application_name"),
+    ("PGOPTIONS", "options"),
+    ("PGTARGETSESSIONATTRS", "target_session_attrs"),
+    ("PGLOADBALANCEHOSTS", "load_balance_hosts"),
+];
+
+/// The parameters set by PG* environment variables.
+///
+/// As in libpq, the settings of the service in `PGSERVICE` win over the other
+/// variables. The user and database default to `postgres`.
+pub fn env_params() -> Result<Params> {
+    let mut params = Params::default();
+    for (var, key) in ENV_VARS {
+        if let Ok(value) = std::env::var(var) {
+            params.set(key, value, var);
+        }
+    }
+    if let Some(name) = params.get("service").map(str::to_string) {
+        params.merge(service(&name)?);
+    }
+    params.set_default("user", "postgres");
+    params.set_default("dbname", "postgres");
+    Ok(params)
+}
+
+/// The parameters of a URL such as `postgres://user@db1,db2:5433/app?sslmode=require`.
+///
+/// As in libpq, query parameters override the rest of the URL, and the settings
+/// of a `service` parameter fill in what the URL leaves out.
+pub fn url_params(url: &str, origin: &str) -> Result<Params> {
+    let (url, hosts) = hosts::split_url_hosts(url);
+    let parsed = url::Url::parse(&url).context("Invalid DATABASE_URL format")?;
+
+    let mut params = Params::default();
+    match hosts {
+        // e.g. `postgres:///app?host=/var/run/postgresql`
+        Some(hosts) if hosts.is_empty() => {}
+        Some(hosts) => params.set("host", hosts::percent_decode(&hosts), origin),
+        None => {
+            // Socket directories are percent-encoded, e.g. `postgres://%2Ftmp/app`
+            let host = parsed.host_str().unwrap_or("localhost");
+            params.set("host", hosts::percent_decode(host), origin);
+            if let Some(port) = parsed.port() {
+                params.set("port", port.to_string(), origin);
+            }
+        }
+    }
+    if !parsed.username().is_empty() {
+        params.set("user", hosts::percent_decode(parsed.username()), origin);
+    }
+    if let Some(password) = parsed.password() {
+        params.set("password", hosts::percent_decode(password), origin);
+    }
+    let database = parsed.path().trim_start_matches('/');
+    if !database.is_empty() {
+        params.set("dbname", hosts::percent_decode(database), origin);
+    }
+    for (key, value) in parsed.query_pairs() {
+        params.set(&key, value, origin);
+    }
+
+    if let Some(name) = params.get("service").map(str::to_string) {
+        params.fill(service(&name)?);
+    }
+    Ok(params)
+}
+
+/// The parameters of connection service `name`.
+///
+/// Looks in `PGSERVICEFILE` (default `~/.pg_service.conf`), then in
+/// `pg_service.conf` in `PGSYSCONFDIR`; the first file defining the service wins.
+pub fn service(name: &str) -> Result<Params> {
+    let user_file = std::env::var_os("PGSERVICEFILE")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".pg_service.conf")));
+    let system_file =
+        std::env::var_os("PGSYSCONFDIR").map(|dir| PathBuf::from(dir).join("pg_service.conf"));
+
+    let files: Vec<PathBuf> = [user_file, system_file].into_iter().flatten().collect();
+    for path in &files {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            continue;
+        };
+        let origin = format!("service {} ({})", name, path.display());
+        if let Some(params) = parse_service_file(&contents, name, &origin)
+            .with_context(|| format!("Invalid service file {}", path.display()))?
+        {
+            return Ok(params);
+        }
+    }
+    let searched: Vec<String> = files
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    anyhow::bail!(
+        "Service '{}' not found (searched: {})",
+        name,
+        searched.join(", ")
+    )
+}
+
+/// The `key=value` lines under `[name]` in an INI-style service file.
+fn parse_service_file(contents: &str, name: &str, origin: &str) -> Result<Option<Params>> {
+    let mut params: Option<Params> = None;
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[') {
+            if params.is_some() {
+                break;
+            }
+            let section = section
+                .strip_suffix(']')
+                .with_context(|| format!("line {}: missing ']'", number + 1))?;
+            if section == name {
+                params = Some(Params::default());
+            }
+            continue;
+        }
+        if let Some(params) = params.as_mut() {
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("line {}: expected key=value", number + 1))?;
+            params.set(key.trim(), value.trim(), origin);
+        }
+    }
+    Ok(params)
+}
+
+/// The password for `user` on `database` at `host`, from `PGPASSFILE` (default
+/// `~/.pgpass`).
+///
+/// As in libpq, lines are `hostname:port:database:username:password`, `*` matches
+/// anything, and a file that others can read is ignored. `localhost` also matches
+/// Unix sockets.
+pub fn password(host: &HostPort, database: &str, user: &str) -> Option<String> {
+    let path = password_file()?;
+    let metadata = std::fs::metadata(&path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    if metadata.permissions().mode() & 0o077 != 0 {
+        tracing::warn!(
+            "Ignoring password file {}: it has group or world access (chmod 0600 fixes it)",
+            path.display()
+        );
+        return None;
+    }
+    let contents = std::fs::read_to_string(&path).ok()?;
+
+    let port = host.port.to_string();
+    let matches = |pattern: &str, value: &str| pattern == "*" || pattern == value;
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .find_map(|line| {
+            let fields = split_pgpass_line(line);
+            let [hostname, line_port, line_database, username, password] = fields.as_slice() else {
+                return None;
+            };
+            let host_matches = matches(hostname, &host.host)
+                || (host.is_socket() && hostname.as_str() == "localhost");
+            (host_matches
+                && matches(line_port, &port)
+                && matches(line_database, database)
+                && matches(username, user)
+                && !password.is_empty())
+            .then(|| password.clone())
+        })
+}
+
+/// `PGPASSFILE`, or `~/.pgpass`.
+pub fn password_file() -> Option<PathBuf> {
+    std::env::var_os("PGPASSFILE")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".pgpass")))
+}
+
+/// The fields of a password file line; `\` escapes `:` and `\`.
+fn split_pgpass_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => fields.last_mut().unwrap().extend(chars.next()),
+            // The password is the rest of the line
+            ':' if fields.len() < 5 => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_pgpass_lines() {
+        assert_eq!(
+            split_pgpass_line("db:5432:app:me:secret"),
+            ["db", "5432", "app", "me", "secret"]
+        );
+        assert_eq!(
+            split_pgpass_line(r"db\:1:*:app:me\\you:a\:b\\c"),
+            ["db:1", "*", "app", r"me\you", r"a:b\c"]
+        );
+        // The password keeps unescaped colons
+        assert_eq!(
+            split_pgpass_line("*:*:*:*:pass:word"),
+            ["*", "*", "*", "*", "pass:word"]
+        );
+        assert_eq!(split_pgpass_line("db:5432"), ["db", "5432"]);
+    }
+
+    #[test]
+    fn parses_service_files() {
+        let contents = "\
+# Services
+[other]
+host=elsewhere
+
+[app]
+host = db1
+ dbname=app=1
+[app]
+host=db2
+";
+        let params = parse_service_file(contents, "app", "service app")
+            .unwrap()
+            .unwrap();
+        assert_eq!(params.get("host"), Some("db1"));
+        assert_eq!(params.get("dbname"), Some("app=1"));
+        assert_eq!(
+            params.origins().next().map(|(_, origin)| origin),
+            Some("service app")
+        );
+        assert!(parse_service_file(contents, "missing", "")
+            .unwrap()
+            .is_none());
+
+        let error = parse_service_file("[app\nhost=db", "app", "").unwrap_err();
+        assert_eq!(error.to_string(), "line 1: missing ']'");
+        let error = parse_service_file("[app]\nhost", "app", "").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: expected key=value");
+        // Lines of other services aren't checked
+        assert!(parse_service_file("[other]\nhost\n[app]\nhost=db", "app", "").is_ok());
+    }
+
+    /// Reads `PGPASSFILE`, `PGSERVICEFILE` and `PGSYSCONFDIR`, so all in one test.
+    #[test]
+    fn reads_files_from_the_environment() {
+        let dir = std::env::temp_dir().join(format!("fgp-libpq-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let pgpass = dir.join("pgpass");
+        std::fs::write(
+            &pgpass,
+            "# comment\n\
+             db1:5432:app:me:\n\
+             db1:5432:app:me:first\n\
+             db1:5432:app:me:second\n\
+             localhost:5433:*:me:socket\n\
+             *:*:*:other:any\n",
+        )
+        .unwrap();
+        std::env::set_var("PGPASSFILE", &pgpass);
+        let host = |host: &str, port| HostPort {
+            host: host.to_string(),
+            port,
+        };
+        let password = |host: &HostPort, user| password(host, "app", user);
+
+        std::fs::set_permissions(&pgpass, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(password(&host("db1", 5432), "me"), None);
+        std::fs::set_permissions(&pgpass, std::fs::Permissions::from_mode(0o600)).unwrap();
+        // Empty passwords are skipped, then the first match wins
+        assert_eq!(password(&host("db1", 5432), "me").as_deref(), Some("first"));
+        assert_eq!(password(&host("db1", 5433), "me"), None);
+        assert_eq!(
+            password(&host("/tmp", 5433), "me").as_deref(),
+            Some("socket")
+        );
+        assert_eq!(
+            password(&host("localhost", 5433), "me").as_deref(),
+            Some("socket")
+        );
+        assert_eq!(
+            password(&host("/tmp", 5432), "other").as_deref(),
+            Some("any")
+        );
+        std::env::remove_var("PGPASSFILE");
+
+        let user_file = dir.join("pg_service.conf");
+        std::fs::write(&user_file, "[app]\nhost=user\n").unwrap();
+        let system_dir = dir.join("etc");
+        std::fs::create_dir_all(&system_dir).unwrap();
+        std::fs::write(
+            system_dir.join("pg_service.conf"),
+            "[app]\nhost=system\n[reports]\nhost=reports\n",
+        )
+        .unwrap();
+        std::env::set_var("PGSERVICEFILE", &user_file);
+        std::env::set_var("PGSYSCONFDIR", &system_dir);
+        assert_eq!(service("app").unwrap().get("host"), Some("user"));
+        assert_eq!(service("reports").unwrap().get("host"), Some("reports"));
+        let error = service("missing").unwrap_err().to_string();
+        assert!(
+            error.starts_with("Service 'missing' not found"),
+            "{}",
+            error
+        );
+        std::env::remove_var("PGSERVICEFILE");
+        std::env::remove_var("PGSYSCONFDIR");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
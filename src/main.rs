@@ -9,24 +9,54 @@
 //! fgp-postgres stop            # Stop daemon
 //! fgp-postgres status          # Check daemon status
 //! fgp-postgres query "SELECT 1" # Quick query (no daemon)
+//! fgp-postgres execute "DELETE FROM t WHERE id = 1" # Quick statement (no daemon)
 //! ```
 
+mod cancel;
+mod classify;
 mod client;
+mod connections;
+mod copy;
+mod cursor;
+mod decode;
+mod error;
+mod guard;
+mod hosts;
+mod libpq;
+mod params;
+mod pool;
+mod reconnect;
+mod replica;
 mod service;
+mod session;
+mod tls;
+mod types;
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use fgp_daemon::{cleanup_socket, FgpServer};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 use std::process::Command;
 
-use crate::client::ConnectionConfig;
+use crate::cancel::Limits;
+use crate::client::{ConnectionConfig, ExecuteOptions, QueryOptions, RowFormat};
+use crate::connections::{mask_password, Connections, DEFAULT_IDLE_POOL_TIMEOUT};
+use crate::copy::{ColumnMapping, CopyFormat, ExportOptions, ImportOptions, OnConflict};
+use crate::guard::GuardConfig;
+use crate::hosts::{LoadBalanceHosts, TargetSessionAttrs};
+use crate::libpq::Params;
+use crate::pool::{ConnectSettings, PoolSettings, Recycling};
 use crate::service::PostgresService;
+use crate::tls::SslMode;
 
 const DEFAULT_SOCKET: &str = "~/.fgp/services/postgres/daemon.sock";
 
+/// Name of the connection configured by DATABASE_URL or PG* variables; `$` keeps
+/// it apart from the names in connections.json.
+const ENV_CONNECTION: &str = "$env";
+
 /// Named connection configuration stored in config file.
 #[derive(Debug, Deserialize, Serialize)]
 struct NamedConnection {
@@ -36,7 +66,32 @@ struct NamedConnection {
     user: Option<String>,
     password: Option<String>,
     database: Option<String>,
+    /// Legacy switch: `true` means `sslmode=require`, `false` means `disable`.
     ssl: Option<bool>,
+    sslmode: Option<SslMode>,
+    sslrootcert: Option<String>,
+    sslcert: Option<String>,
+    sslkey: Option<String>,
+    /// Connection service whose settings fill in the fields left out (without `url`).
+    service: Option<String>,
+    /// Refuse writes and run every transaction read-only.
+    #[serde(default)]
+    read_only: bool,
+    /// Destructive statements that need confirmation: `true` for all kinds, or a list.
+    #[serde(default)]
+    guard: GuardConfig,
+    /// Start even if the database can't be reached, and connect in the background.
+    #[serde(default)]
+    lazy: bool,
+    /// Standbys that serve reads: `host[:port]` sharing everything else, or a URL.
+    #[serde(default)]
+    replicas: Vec<String>,
+    /// Most replay lag a replica may have and still serve reads.
+    max_lag_ms: Option<u64>,
+    #[serde(flatten)]
+    connect: ConnectSettings,
+    #[serde(default)]
+    pool: PoolSettings,
 }
 
 /// Config file structure for named connections.
@@ -45,41 +100,266 @@ struct ConnectionsConfig {
     #[serde(default)]
     connections: HashMap<String, NamedConnection>,
     default: Option<String>,
+    /// Close a daemon's pools unused for this long (0: never).
+    idle_pool_timeout_ms: Option<u64>,
+}
+
+impl NamedConnection {
+    fn to_config(&self) -> Result<ConnectionConfig> {
+        Ok(self.resolve()?.0)
+    }
+
+    /// The connection's config, and where each setting came from.
+    fn resolve(&self) -> Result<(ConnectionConfig, Origins)> {
+        let params = match &self.url {
+            Some(url) => libpq::url_params(url, CONFIG_FILE_ORIGIN)?,
+            None => self.params()?,
+        };
+        let mut config = ConnectionConfig::from_params(&params)?;
+        config.read_only = self.read_only;
+        config.guard = self.guard.clone();
+        config.lazy = self.lazy;
+        config.replicas = self.replicas.clone();
+        config.max_lag_ms = self.max_lag_ms;
+        // Fields next to the URL override its query parameters
+        config.connect.merge(&self.connect);
+        config.pool.merge(&self.pool);
+
+        let mut origins = origins(&params);
+        for (key, _) in self.connect.params().into_iter().chain(self.pool.params()) {
+            origins.insert(key, CONFIG_FILE_ORIGIN.into());
+        }
+        Ok((config, origins))
+    }
+
+    /// The connection fields as libpq parameters; its service fills in the rest.
+    fn params(&self) -> Result<Params> {
+        let sslmode = self.sslmode.or(self.ssl.map(|ssl| {
+            if ssl {
+                SslMode::Require
+            } else {
+                SslMode::Disable
+            }
+        }));
+        let fields = [
+            ("host", self.host.clone()),
+            ("port", self.port.map(|port| port.to_string())),
+            ("user", self.user.clone()),
+            ("password", self.password.clone()),
+            ("dbname", self.database.clone()),
+            ("sslmode", sslmode.map(|mode| mode.as_str().to_string())),
+            ("sslrootcert", self.sslrootcert.clone()),
+            ("sslcert", self.sslcert.clone()),
+            ("sslkey", self.sslkey.clone()),
+        ];
+        let mut params = Params::default();
+        for (key, value) in fields {
+            if let Some(value) = value {
+                params.set(key, value, CONFIG_FILE_ORIGIN);
+            }
+        }
+        if let Some(service) = &self.service {
+            params.fill(libpq::service(service)?);
+        }
+        params.set_default("user", "postgres");
+        params.set_default("dbname", "postgres");
+        Ok(params)
+    }
+}
+
+/// Read connections.json, if there is one.
+fn load_connections() -> Result<Option<ConnectionsConfig>> {
+    let config_path = shellexpand::tilde("~/.fgp/auth/postgres/connections.json").to_string();
+    match std::fs::read_to_string(&config_path) {
+        Ok(config_str) => Ok(Some(
+            serde_json::from_str(&config_str).context("Failed to parse connections.json")?,
+        )),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Where each setting of a connection came from, by URL parameter name; settings
+/// left out are defaults.
+type Origins = BTreeMap<String, String>;
+
+/// Origin of the settings in connections.json.
+const CONFIG_FILE_ORIGIN: &str = "connections.json";
+
+/// Origin of the settings given as flags.
+const COMMAND_LINE_ORIGIN: &str = "command line";
+
+fn origins(params: &Params) -> Origins {
+    params
+        .origins()
+        .map(|(key, origin)| (key.to_string(), origin.to_string()))
+        .collect()
+}
+
+/// A connection, and where it and its settings came from.
+struct Resolved {
+    name: String,
+    /// The source that supplied the connection.
+    source: &'static str,
+    config: ConnectionConfig,
+    origins: Origins,
+}
+
+impl Resolved {
+    /// Apply the flags given to a command, which win over every other source.
+    fn apply(&mut self, settings: &SettingsArgs) {
+        let connect = settings.connect_settings();
+        let pool = settings.pool_settings();
+        for (key, _) in connect.params().into_iter().chain(pool.params()) {
+            self.origins.insert(key, COMMAND_LINE_ORIGIN.into());
+        }
+        self.config.connect.merge(&connect);
+        self.config.pool.merge(&pool);
+    }
+
+    /// Each setting with its value and origin, and the final connection URL.
+    fn explain(&self) -> Result<serde_json::Value> {
+        let config = &self.config;
+        let origin = |key: &str| self.origins.get(key).map_or("default", String::as_str);
+        let mut fields = serde_json::Map::new();
+        let mut field = |key: &str, value: serde_json::Value, origin: &str| {
+            fields.insert(
+                key.into(),
+                serde_json::json!({"value": value, "origin": origin}),
+            );
+        };
+
+        let hosts: Vec<String> = config.hosts()?.iter().map(ToString::to_string).collect();
+        field("host", hosts.into(), origin("host"));
+        field("port", config.port.into(), origin("port"));
+        let user_origin = match config.user.as_str() {
+            "" => "default (login name)",
+            _ => origin("user"),
+        };
+        field("user", config.effective_user().into(), user_origin);
+        let database_origin = match config.database.as_str() {
+            "" => "default (user name)",
+            _ => origin("dbname"),
+        };
+        field(
+            "dbname",
+            config.effective_database().into(),
+            database_origin,
+        );
+        let password_file = libpq::password_file()
+            .map(|path| format!("password file ({})", path.display()))
+            .unwrap_or_default();
+        let (password, password_origin) = match &config.password {
+            Some(_) => (Some("***"), origin("password")),
+            None => {
+                // Looked up for each host when connecting
+                let host = &config.hosts()?[0];
+                let found =
+                    libpq::password(host, &config.effective_database(), &config.effective_user());
+                match found {
+                    Some(_) => (Some("***"), password_file.as_str()),
+                    None => (None, "none"),
+                }
+            }
+        };
+        field("password", password.into(), password_origin);
+        field("sslmode", config.sslmode.as_str().into(), origin("sslmode"));
+        let files = [
+            ("sslrootcert", &config.sslrootcert),
+            ("sslcert", &config.sslcert),
+            ("sslkey", &config.sslkey),
+        ];
+        for (key, file) in files {
+            if let Some(file) = file {
+                field(key, file.as_str().into(), origin(key));
+            }
+        }
+        for (key, value) in config
+            .connect
+            .params()
+            .into_iter()
+            .chain(config.pool.params())
+        {
+            field(&key, value, origin(&key));
+        }
+
+        Ok(serde_json::json!({
+            "connection": self.name,
+            "source": self.source,
+            "dsn": config.masked_url()?,
+            "fields": fields,
+        }))
+    }
 }
 
 /// Resolve connection configuration from multiple sources.
-/// Priority: explicit params → env vars → config file default
 fn resolve_connection(name: Option<&str>) -> Result<ConnectionConfig> {
-    // 1. Try DATABASE_URL env var first (standard convention)
+    resolve(name).map(|resolved| resolved.config)
+}
+
+/// Resolve the connection to use, and where its settings came from.
+///
+/// The first source configured wins: the named connection asked for, DATABASE_URL,
+/// PG* variables (with PGHOST or PGSERVICE set), then the default connection in
+/// connections.json. A connection from the environment is named `ENV_CONNECTION`.
+fn resolve(name: Option<&str>) -> Result<Resolved> {
+    let file = load_connections()?;
+
+    // 1. An explicit --connection, which never falls back to anything else
+    if let Some(name) = name {
+        let conn = file
+            .as_ref()
+            .and_then(|file| file.connections.get(name))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Unknown connection '{}' (fgp-postgres connections lists them)",
+                    name
+                )
+            })?;
+        let (config, origins) = conn
+            .resolve()
+            .with_context(|| format!("Invalid connection '{}'", name))?;
+        return Ok(Resolved {
+            name: name.to_string(),
+            source: "--connection",
+            config,
+            origins,
+        });
+    }
+
+    // 2. DATABASE_URL (standard convention)
     if let Ok(url) = std::env::var("DATABASE_URL") {
-        return ConnectionConfig::from_url(&url);
+        let params = libpq::url_params(&url, "DATABASE_URL")?;
+        return Ok(Resolved {
+            name: ENV_CONNECTION.into(),
+            source: "DATABASE_URL",
+            config: ConnectionConfig::from_params(&params)?,
+            origins: origins(&params),
+        });
     }
 
-    // 2. Try libpq-style env vars
-    if std::env::var("PGHOST").is_ok() {
-        return ConnectionConfig::from_env();
+    // 3. libpq-style env vars
+    if std::env::var("PGHOST").is_ok() || std::env::var("PGSERVICE").is_ok() {
+        let params = libpq::env_params()?;
+        return Ok(Resolved {
+            name: ENV_CONNECTION.into(),
+            source: "PG* variables",
+            config: ConnectionConfig::from_params(&params)?,
+            origins: origins(&params),
+        });
     }
 
-    // 3. Try config file
-    let config_path = shellexpand::tilde("~/.fgp/auth/postgres/connections.json").to_string();
-    if let Ok(config_str) = std::fs::read_to_string(&config_path) {
-        let config: ConnectionsConfig = serde_json::from_str(&config_str)
-            .context("Failed to parse connections.json")?;
-
-        // Use named connection or default
-        let conn_name = name.or(config.default.as_deref());
-        if let Some(name) = conn_name {
-            if let Some(conn) = config.connections.get(name) {
-                if let Some(url) = &conn.url {
-                    return ConnectionConfig::from_url(url);
-                }
-                return Ok(ConnectionConfig {
-                    host: conn.host.clone().unwrap_or_else(|| "localhost".into()),
-                    port: conn.port.unwrap_or(5432),
-                    user: conn.user.clone().unwrap_or_else(|| "postgres".into()),
-                    password: conn.password.clone(),
-                    database: conn.database.clone().unwrap_or_else(|| "postgres".into()),
-                    ssl: conn.ssl.unwrap_or(false),
+    // 4. The default connection in the config file
+    if let Some(file) = &file {
+        if let Some(name) = &file.default {
+            if let Some(conn) = file.connections.get(name) {
+                let (config, origins) = conn
+                    .resolve()
+                    .with_context(|| format!("Invalid connection '{}'", name))?;
+                return Ok(Resolved {
+                    name: name.clone(),
+                    source: "connections.json default",
+                    config,
+                    origins,
                 });
             }
         }
@@ -115,6 +395,21 @@ enum Commands {
         /// Named connection from config file
         #[arg(short, long)]
         connection: Option<String>,
+
+        /// Refuse writes and run every transaction read-only
+        #[arg(long)]
+        read_only: bool,
+
+        /// Start even if the database can't be reached, and connect in the background
+        #[arg(long)]
+        lazy: bool,
+
+        /// Close pools unused for this long (0: never; default: 600000)
+        #[arg(long)]
+        idle_pool_timeout_ms: Option<u64>,
+
+        #[command(flatten)]
+        settings: SettingsArgs,
     },
 
     /// Stop the running daemon
@@ -136,6 +431,136 @@ enum Commands {
         /// SQL query to execute
         sql: String,
 
+        /// Positional parameters as a JSON array, bound to $1, $2, ...
+        #[arg(short, long)]
+        params: Option<String>,
+
+        /// Describe each result column instead of listing names
+        #[arg(long)]
+        column_metadata: bool,
+
+        /// Row layout: objects (keyed by column name) or arrays
+        #[arg(long, default_value = "objects")]
+        row_format: RowFormat,
+
+        /// Return at most this many rows
+        #[arg(long, value_parser = clap::value_parser!(i64).range(1..))]
+        max_rows: Option<i64>,
+
+        /// Named connection from config file
+        #[arg(short, long)]
+        connection: Option<String>,
+    },
+
+    /// Run a quick INSERT/UPDATE/DELETE or DDL statement without starting daemon
+    Execute {
+        /// SQL statement to execute
+        sql: String,
+
+        /// Positional parameters as a JSON array, bound to $1, $2, ...
+        #[arg(short, long)]
+        params: Option<String>,
+
+        /// Return the statement's rows (e.g. from RETURNING)
+        #[arg(long)]
+        returning: bool,
+
+        /// Report what the statement would do, then roll back
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Named connection from config file
+        #[arg(short, long)]
+        connection: Option<String>,
+    },
+
+    /// Export a query or table to a file with COPY
+    Export {
+        /// File to write
+        path: String,
+
+        /// Query to export
+        #[arg(long, conflicts_with = "table", required_unless_present = "table")]
+        sql: Option<String>,
+
+        /// Table to export instead of a query
+        #[arg(short, long)]
+        table: Option<String>,
+
+        /// Schema of --table
+        #[arg(short = 'S', long, default_value = "public")]
+        schema: String,
+
+        /// csv, tsv, ndjson or binary
+        #[arg(short, long, default_value = "csv")]
+        format: CopyFormat,
+
+        /// Leave out the header line (csv, tsv)
+        #[arg(long)]
+        no_header: bool,
+
+        /// CSV field delimiter
+        #[arg(short, long)]
+        delimiter: Option<char>,
+
+        /// Replace the file if it exists
+        #[arg(long)]
+        overwrite: bool,
+
+        /// Named connection from config file
+        #[arg(short, long)]
+        connection: Option<String>,
+    },
+
+    /// Load a CSV or NDJSON file into a table with COPY
+    Import {
+        /// File to read
+        path: String,
+
+        /// Target table
+        #[arg(short, long)]
+        table: String,
+
+        /// Schema of the table
+        #[arg(short = 'S', long, default_value = "public")]
+        schema: String,
+
+        /// csv or ndjson
+        #[arg(short, long, default_value = "csv")]
+        format: CopyFormat,
+
+        /// The CSV file has no header line
+        #[arg(long)]
+        no_header: bool,
+
+        /// CSV field delimiter
+        #[arg(short, long)]
+        delimiter: Option<char>,
+
+        /// Columns in file order (a,b,c), or renames of source fields (src=a,other=b)
+        #[arg(long)]
+        columns: Option<ColumnMapping>,
+
+        /// error, skip or update
+        #[arg(long, default_value = "error")]
+        on_conflict: OnConflict,
+
+        /// Conflict target for --on-conflict update (default: primary key)
+        #[arg(long, value_delimiter = ',')]
+        conflict_columns: Vec<String>,
+
+        /// Don't wrap the import in a transaction
+        #[arg(long)]
+        no_transaction: bool,
+
+        /// Empty the table before loading
+        #[arg(long)]
+        truncate: bool,
+
+        /// Load and report, then roll back
+        #[arg(long)]
+        dry_run: bool,
+
         /// Named connection from config file
         #[arg(short, long)]
         connection: Option<String>,
@@ -154,6 +579,94 @@ enum Commands {
 
     /// List configured connections
     Connections,
+
+    /// Explain which connection commands use, and where each setting comes from
+    Resolve {
+        /// Named connection from config file
+        #[arg(short, long)]
+        connection: Option<String>,
+
+        #[command(flatten)]
+        settings: SettingsArgs,
+    },
+}
+
+/// Pool and connection settings for `start` and `resolve`; they override the named
+/// connection and URL.
+#[derive(clap::Args)]
+struct SettingsArgs {
+    /// Most connections the pool opens at once
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pool_max_size: Option<u64>,
+
+    /// How long a request waits for a free connection
+    #[arg(long)]
+    pool_wait_timeout_ms: Option<u64>,
+
+    /// How long opening a new connection may take
+    #[arg(long)]
+    pool_create_timeout_ms: Option<u64>,
+
+    /// How long checking a returned connection may take
+    #[arg(long)]
+    pool_recycle_timeout_ms: Option<u64>,
+
+    /// fast, verified or clean
+    #[arg(long)]
+    pool_recycling: Option<Recycling>,
+
+    /// Seconds to wait while connecting (0: no limit)
+    #[arg(long)]
+    connect_timeout: Option<u64>,
+
+    /// Turn off TCP keepalives
+    #[arg(long)]
+    no_keepalives: bool,
+
+    /// Seconds of inactivity before a TCP keepalive is sent
+    #[arg(long)]
+    keepalives_idle: Option<u64>,
+
+    /// Shown in pg_stat_activity and server logs
+    #[arg(long)]
+    application_name: Option<String>,
+
+    /// Server options, e.g. "-c statement_timeout=30s"
+    #[arg(long, allow_hyphen_values = true)]
+    options: Option<String>,
+
+    /// Which of several hosts to use: any, read-write, read-only, primary, standby
+    /// or prefer-standby
+    #[arg(long)]
+    target_session_attrs: Option<TargetSessionAttrs>,
+
+    /// disable, or random to spread connections over the hosts
+    #[arg(long)]
+    load_balance_hosts: Option<LoadBalanceHosts>,
+}
+
+impl SettingsArgs {
+    fn pool_settings(&self) -> PoolSettings {
+        PoolSettings {
+            max_size: self.pool_max_size.map(|n| n as usize),
+            wait_timeout_ms: self.pool_wait_timeout_ms,
+            create_timeout_ms: self.pool_create_timeout_ms,
+            recycle_timeout_ms: self.pool_recycle_timeout_ms,
+            recycling: self.pool_recycling,
+        }
+    }
+
+    fn connect_settings(&self) -> ConnectSettings {
+        ConnectSettings {
+            connect_timeout: self.connect_timeout,
+            keepalives: self.no_keepalives.then_some(false),
+            keepalives_idle: self.keepalives_idle,
+            application_name: self.application_name.clone(),
+            options: self.options.clone(),
+            target_session_attrs: self.target_session_attrs,
+            load_balance_hosts: self.load_balance_hosts,
+        }
+    }
 }
 
 fn main() -> Result<()> {
@@ -164,16 +677,126 @@ fn main() -> Result<()> {
             socket,
             foreground,
             connection,
-        } => cmd_start(socket, foreground, connection),
+            read_only,
+            lazy,
+            idle_pool_timeout_ms,
+            settings,
+        } => cmd_start(
+            socket,
+            foreground,
+            connection,
+            read_only,
+            lazy,
+            idle_pool_timeout_ms,
+            settings,
+        ),
         Commands::Stop { socket } => cmd_stop(socket),
         Commands::Status { socket } => cmd_status(socket),
-        Commands::Query { sql, connection } => cmd_query(sql, connection),
+        Commands::Query {
+            sql,
+            params,
+            column_metadata,
+            row_format,
+            max_rows,
+            connection,
+        } => cmd_query(
+            sql,
+            params,
+            QueryOptions {
+                column_metadata,
+                row_format,
+                max_rows,
+                cursor: false,
+                limits: Limits::default(),
+            },
+            connection,
+        ),
+        Commands::Execute {
+            sql,
+            params,
+            returning,
+            dry_run,
+            connection,
+        } => cmd_execute(
+            sql,
+            params,
+            ExecuteOptions {
+                returning,
+                dry_run,
+                limits: Limits::default(),
+            },
+            connection,
+        ),
+        Commands::Export {
+            path,
+            sql,
+            table,
+            schema,
+            format,
+            no_header,
+            delimiter,
+            overwrite,
+            connection,
+        } => {
+            let sql = match (sql, table) {
+                (Some(sql), _) => sql,
+                (None, Some(table)) => copy::table_query(&schema, &table),
+                (None, None) => unreachable!("clap requires --sql or --table"),
+            };
+            let options = ExportOptions {
+                format,
+                header: !no_header,
+                delimiter,
+                overwrite,
+            };
+            cmd_export(sql, path, options, connection)
+        }
+        Commands::Import {
+            path,
+            table,
+            schema,
+            format,
+            no_header,
+            delimiter,
+            columns,
+            on_conflict,
+            conflict_columns,
+            no_transaction,
+            truncate,
+            dry_run,
+            connection,
+        } => {
+            let options = ImportOptions {
+                format,
+                header: !no_header,
+                delimiter,
+                columns,
+                on_conflict,
+                conflict_columns,
+                transaction: !no_transaction,
+                truncate,
+                dry_run,
+            };
+            cmd_import(path, schema, table, options, connection)
+        }
         Commands::Tables { schema, connection } => cmd_tables(schema, connection),
         Commands::Connections => cmd_connections(),
+        Commands::Resolve {
+            connection,
+            settings,
+        } => cmd_resolve(connection, settings),
     }
 }
 
-fn cmd_start(socket: String, foreground: bool, connection: Option<String>) -> Result<()> {
+fn cmd_start(
+    socket: String,
+    foreground: bool,
+    connection: Option<String>,
+    read_only: bool,
+    lazy: bool,
+    idle_pool_timeout_ms: Option<u64>,
+    settings: SettingsArgs,
+) -> Result<()> {
     let socket_path = shellexpand::tilde(&socket).to_string();
 
     // Create parent directory
@@ -181,24 +804,61 @@ fn cmd_start(socket: String, foreground: bool, connection: Option<String>) -> Re
         std::fs::create_dir_all(parent).context("Failed to create socket directory")?;
     }
 
-    // Resolve connection BEFORE fork
-    let config = resolve_connection(connection.as_deref())?;
+    // Resolve connections BEFORE fork
+    let Resolved { name, config, .. } = resolve(connection.as_deref())?;
+    let file = load_connections()?.unwrap_or_default();
+    let mut configs = BTreeMap::new();
+    for (other, conn) in &file.connections {
+        // Only the connection asked for has to be valid
+        match conn.to_config() {
+            Ok(other_config) => {
+                configs.insert(other.clone(), other_config);
+            }
+            Err(e) => eprintln!("Warning: skipping invalid connection '{}': {:#}", other, e),
+        }
+    }
+    configs.insert(name.clone(), config);
+    // Flags apply to every connection the daemon serves
+    for config in configs.values_mut() {
+        config.read_only |= read_only;
+        config.lazy |= lazy;
+        config.connect.merge(&settings.connect_settings());
+        config.pool.merge(&settings.pool_settings());
+    }
+    let idle_timeout = match idle_pool_timeout_ms.or(file.idle_pool_timeout_ms) {
+        Some(0) => None,
+        Some(ms) => Some(std::time::Duration::from_millis(ms)),
+        None => Some(DEFAULT_IDLE_POOL_TIMEOUT),
+    };
 
     let pid_file = format!("{}.pid", socket_path);
 
+    let config = &configs[&name];
     println!("Starting fgp-postgres daemon...");
     println!("Socket: {}", socket_path);
+    let hosts: Vec<String> = config.hosts()?.iter().map(ToString::to_string).collect();
     println!(
-        "Database: {}@{}:{}/{}",
-        config.user, config.host, config.port, config.database
+        "Database: {}@{}/{}",
+        config.user,
+        hosts.join(","),
+        config.database
     );
+    if configs.len() > 1 {
+        let names: Vec<&str> = configs.keys().map(String::as_str).collect();
+        println!("Connections: {} (default: {})", names.join(", "), name);
+    }
+    if config.read_only {
+        println!("Mode: read-only");
+    }
+    let connections = Connections::new(name, configs, idle_timeout);
 
     if foreground {
         tracing_subscriber::fmt()
             .with_env_filter("fgp_postgres=debug,fgp_daemon=debug")
             .init();
 
-        let service = PostgresService::new(config).context("Failed to create PostgresService")?;
+        let service =
+            PostgresService::new(connections).context("Failed to create PostgresService")?;
         let server =
             FgpServer::new(service, &socket_path).context("Failed to create FGP server")?;
         server.serve().context("Server error")?;
@@ -215,8 +875,8 @@ fn cmd_start(socket: String, foreground: bool, connection: Option<String>) -> Re
                     .with_env_filter("fgp_postgres=debug,fgp_daemon=debug")
                     .init();
 
-                let service =
-                    PostgresService::new(config).context("Failed to create PostgresService")?;
+                let service = PostgresService::new(connections)
+                    .context("Failed to create PostgresService")?;
                 let server =
                     FgpServer::new(service, &socket_path).context("Failed to create FGP server")?;
                 server.serve().context("Server error")?;
@@ -320,15 +980,91 @@ fn cmd_status(socket: String) -> Result<()> {
     Ok(())
 }
 
-fn cmd_query(sql: String, connection: Option<String>) -> Result<()> {
+fn cmd_query(
+    sql: String,
+    params: Option<String>,
+    options: QueryOptions,
+    connection: Option<String>,
+) -> Result<()> {
     let config = resolve_connection(connection.as_deref())?;
+    let params: Vec<serde_json::Value> = match params {
+        Some(p) => serde_json::from_str(&p).context("--params must be a JSON array")?,
+        None => Vec::new(),
+    };
 
     // Create a temporary runtime for the one-shot query
     let rt = tokio::runtime::Runtime::new()?;
-    let result = rt.block_on(async {
-        let client = crate::client::PostgresClient::new(config).await?;
-        client.query(&sql, &[]).await
-    })?;
+    let result = rt
+        .block_on(async {
+            let client = crate::client::PostgresClient::new(config).await?;
+            client.query(&sql, &params, &options).await
+        })
+        .map_err(|e| error::structured(e, Some(&sql)))?;
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+fn cmd_execute(
+    sql: String,
+    params: Option<String>,
+    options: ExecuteOptions,
+    connection: Option<String>,
+) -> Result<()> {
+    let config = resolve_connection(connection.as_deref())?;
+    let params: Vec<serde_json::Value> = match params {
+        Some(p) => serde_json::from_str(&p).context("--params must be a JSON array")?,
+        None => Vec::new(),
+    };
+
+    let rt = tokio::runtime::Runtime::new()?;
+    let result = rt
+        .block_on(async {
+            let client = crate::client::PostgresClient::new(config).await?;
+            client.execute(&sql, &params, &options).await
+        })
+        .map_err(|e| error::structured(e, Some(&sql)))?;
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+fn cmd_export(
+    sql: String,
+    path: String,
+    options: ExportOptions,
+    connection: Option<String>,
+) -> Result<()> {
+    let config = resolve_connection(connection.as_deref())?;
+
+    let rt = tokio::runtime::Runtime::new()?;
+    let result = rt
+        .block_on(async {
+            let client = crate::client::PostgresClient::new(config).await?;
+            client.export(&sql, &path, &options).await
+        })
+        .map_err(|e| error::structured(e, Some(&sql)))?;
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+fn cmd_import(
+    path: String,
+    schema: String,
+    table: String,
+    options: ImportOptions,
+    connection: Option<String>,
+) -> Result<()> {
+    let config = resolve_connection(connection.as_deref())?;
+
+    let rt = tokio::runtime::Runtime::new()?;
+    let result = rt
+        .block_on(async {
+            let client = crate::client::PostgresClient::new(config).await?;
+            client.import(&path, &schema, &table, &options).await
+        })
+        .map_err(|e| error::structured(e, None))?;
 
     println!("{}", serde_json::to_string_pretty(&result)?);
     Ok(())
@@ -347,6 +1083,13 @@ fn cmd_tables(schema: String, connection: Option<String>) -> Result<()> {
     Ok(())
 }
 
+fn cmd_resolve(connection: Option<String>, settings: SettingsArgs) -> Result<()> {
+    let mut resolved = resolve(connection.as_deref())?;
+    resolved.apply(&settings);
+    println!("{}", serde_json::to_string_pretty(&resolved.explain()?)?);
+    Ok(())
+}
+
 fn cmd_connections() -> Result<()> {
     let config_path = shellexpand::tilde("~/.fgp/auth/postgres/connections.json").to_string();
 
@@ -361,16 +1104,7 @@ fn cmd_connections() -> Result<()> {
                     ""
                 };
                 if let Some(url) = &conn.url {
-                    // Mask password in URL
-                    let masked = url::Url::parse(url)
-                        .map(|mut u| {
-                            if u.password().is_some() {
-                                let _ = u.set_password(Some("***"));
-                            }
-                            u.to_string()
-                        })
-                        .unwrap_or_else(|_| url.clone());
-                    println!("  {}{}: {}", name, default_marker, masked);
+                    println!("  {}{}: {}", name, default_marker, mask_password(url));
                 } else {
                     println!(
                         "  {}{}: {}@{}:{}/{}",
@@ -0,0 +1,363 @@
+//! Binding of JSON request parameters to typed statement placeholders.
+
+use anyhow::Result;
+use bytes::BytesMut;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
+use postgres_protocol::types::{array_to_sql, ArrayDimension};
+use serde_json::Value;
+use std::error::Error;
+use tokio_postgres::types::{to_sql_checked, IsNull, Kind, ToSql, Type};
+
+use crate::types::{type_name, Numeric};
+
+/// A JSON value coerced to the Postgres type of its placeholder.
+#[derive(Debug)]
+pub enum SqlParam {
+    Null,
+    Bool(bool),
+    Char(i8),
+    Int2(i16),
+    Int4(i32),
+    Int8(i64),
+    Oid(u32),
+    Float4(f32),
+    Float8(f64),
+    Numeric(Numeric),
+    Text(String),
+    Json(Value),
+    Bytea(Vec<u8>),
+    Uuid(uuid::Uuid),
+    Date(NaiveDate),
+    Time(NaiveTime),
+    Timestamp(NaiveDateTime),
+    TimestampTz(DateTime<FixedOffset>),
+    Array {
+        dimensions: Vec<i32>,
+        elements: Vec<SqlParam>,
+    },
+}
+
+/// Convert JSON values into parameters matching the types reported by `prepare`.
+///
+/// Errors name the 1-based placeholder and the type the server expects.
+pub fn bind_params(values: &[Value], types: &[Type]) -> Result<Vec<SqlParam>> {
+    if values.len() != types.len() {
+        anyhow::bail!(
+            "Statement expects {} parameter(s), got {}",
+            types.len(),
+            values.len()
+        );
+    }
+
+    values
+        .iter()
+        .zip(types)
+        .enumerate()
+        .map(|(i, (value, ty))| {
+            SqlParam::from_json(value, ty).map_err(|e| {
+                anyhow::anyhow!("Parameter ${} (expected {}): {}", i + 1, type_name(ty), e)
+            })
+        })
+        .collect()
+}
+
+/// Borrow bound parameters in the form tokio-postgres expects.
+pub fn as_refs(params: &[SqlParam]) -> Vec<&(dyn ToSql + Sync)> {
+    params.iter().map(|p| p as &(dyn ToSql + Sync)).collect()
+}
+
+/// Strip domains down to the type that defines the wire format.
+fn base_type(ty: &Type) -> &Type {
+    match ty.kind() {
+        Kind::Domain(base) => base_type(base),
+        _ => ty,
+    }
+}
+
+impl SqlParam {
+    /// Coerce a JSON value to the given Postgres type.
+    fn from_json(value: &Value, ty: &Type) -> Result<Self, String> {
+        if value.is_null() {
+            return Ok(SqlParam::Null);
+        }
+
+        let ty = base_type(ty);
+        if let Kind::Array(member) = ty.kind() {
+            return Self::array_from_json(value, member);
+        }
+        if let Kind::Enum(_) = ty.kind() {
+            return Self::text_from_json(value);
+        }
+
+        match *ty {
+            Type::BOOL => match value {
+                Value::Bool(b) => Ok(SqlParam::Bool(*b)),
+                Value::String(s) => match s.to_ascii_lowercase().as_str() {
+                    "true" | "t" => Ok(SqlParam::Bool(true)),
+                    "false" | "f" => Ok(SqlParam::Bool(false)),
+                    _ => Err(mismatch(value)),
+                },
+                _ => Err(mismatch(value)),
+            },
+            Type::CHAR => match value.as_str().map(str::as_bytes) {
+                Some([b]) if b.is_ascii() => Ok(SqlParam::Char(*b as i8)),
+                _ => Err(mismatch(value)),
+            },
+            Type::INT2 => int_from_json(value).map(SqlParam::Int2),
+            Type::INT4 => int_from_json(value).map(SqlParam::Int4),
+            Type::INT8 => int_from_json(value).map(SqlParam::Int8),
+            Type::OID => int_from_json(value).map(SqlParam::Oid),
+            Type::FLOAT4 => float_from_json(value).map(|f| SqlParam::Float4(f as f32)),
+            Type::FLOAT8 => float_from_json(value).map(SqlParam::Float8),
+            Type::NUMERIC => match value {
+                Value::Number(n) => Numeric::parse(&n.to_string()).map(SqlParam::Numeric),
+                Value::String(s) => Numeric::parse(s).map(SqlParam::Numeric),
+                _ => Err(mismatch(value)),
+            },
+            Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN | Type::XML => {
+                Self::text_from_json(value)
+            }
+            Type::JSON | Type::JSONB => Ok(SqlParam::Json(value.clone())),
+            Type::BYTEA => bytea_from_json(value).map(SqlParam::Bytea),
+            Type::UUID => match value {
+                Value::String(s) => uuid::Uuid::parse_str(s)
+                    .map(SqlParam::Uuid)
+                    .map_err(|e| e.to_string()),
+                _ => Err(mismatch(value)),
+            },
+            Type::DATE => parse_str(value, |s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok())
+                .map(SqlParam::Date),
+            Type::TIME => parse_str(value, |s| NaiveTime::parse_from_str(s, "%H:%M:%S%.f").ok())
+                .map(SqlParam::Time),
+            Type::TIMESTAMP => parse_str(value, |s| {
+                parse_naive_datetime(s).or_else(|| {
+                    DateTime::parse_from_rfc3339(s)
+                        .ok()
+                        .map(|dt| dt.naive_local())
+                })
+            })
+            .map(SqlParam::Timestamp),
+            Type::TIMESTAMPTZ => parse_str(value, |s| {
+                DateTime::parse_from_rfc3339(s)
+                    .ok()
+                    .or_else(|| parse_naive_datetime(s).map(|dt| dt.and_utc().fixed_offset()))
+            })
+            .map(SqlParam::TimestampTz),
+            _ if ty.name() == "citext" => Self::text_from_json(value),
+            _ => Err(format!(
+                "unsupported parameter type; cast the placeholder instead, e.g. $n::text::{}",
+                ty.name()
+            )),
+        }
+    }
+
+    fn text_from_json(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::String(s) => Ok(SqlParam::Text(s.clone())),
+            Value::Number(n) => Ok(SqlParam::Text(n.to_string())),
+            Value::Bool(b) => Ok(SqlParam::Text(b.to_string())),
+            _ => Err(mismatch(value)),
+        }
+    }
+
+    /// Flatten a (possibly nested) JSON array into Postgres' row-major layout.
+    fn array_from_json(value: &Value, member: &Type) -> Result<Self, String> {
+        let Value::Array(items) = value else {
+            return Err(mismatch(value));
+        };
+
+        // JSON members take nested arrays as element values, not dimensions.
+        let nested = !matches!(*base_type(member), Type::JSON | Type::JSONB)
+            && items.first().is_some_and(Value::is_array);
+
+        if !nested {
+            let elements = items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    Self::from_json(item, member).map_err(|e| format!("element [{}]: {}", i, e))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let dimensions = if elements.is_empty() {
+                Vec::new()
+            } else {
+                vec![elements.len() as i32]
+            };
+            return Ok(SqlParam::Array {
+                dimensions,
+                elements,
+            });
+        }
+
+        let mut inner_dimensions: Option<Vec<i32>> = None;
+        let mut elements = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            match Self::array_from_json(item, member)
+                .map_err(|e| format!("element [{}]: {}", i, e))?
+            {
+                SqlParam::Array {
+                    dimensions,
+                    elements: inner,
+                } => {
+                    if inner_dimensions.get_or_insert_with(|| dimensions.clone()) != &dimensions {
+                        return Err(
+                            "multidimensional arrays must have matching sub-array lengths".into(),
+                        );
+                    }
+                    elements.extend(inner);
+                }
+                _ => unreachable!("array_from_json always returns an array"),
+            }
+        }
+
+        let mut dimensions = vec![items.len() as i32];
+        dimensions.extend(inner_dimensions.unwrap_or_default());
+        if dimensions.len() > 1 && dimensions[1..].contains(&0) {
+            return Err("multidimensional arrays cannot contain empty sub-arrays".into());
+        }
+        Ok(SqlParam::Array {
+            dimensions,
+            elements,
+        })
+    }
+}
+
+impl ToSql for SqlParam {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        let ty = base_type(ty);
+        match self {
+            SqlParam::Null => Ok(IsNull::Yes),
+            SqlParam::Bool(v) => v.to_sql(ty, out),
+            SqlParam::Char(v) => v.to_sql(ty, out),
+            SqlParam::Int2(v) => v.to_sql(ty, out),
+            SqlParam::Int4(v) => v.to_sql(ty, out),
+            SqlParam::Int8(v) => v.to_sql(ty, out),
+            SqlParam::Oid(v) => v.to_sql(ty, out),
+            SqlParam::Float4(v) => v.to_sql(ty, out),
+            SqlParam::Float8(v) => v.to_sql(ty, out),
+            SqlParam::Numeric(v) => v.to_sql(ty, out),
+            SqlParam::Text(v) => {
+                postgres_protocol::types::text_to_sql(v, out);
+                Ok(IsNull::No)
+            }
+            SqlParam::Json(v) => v.to_sql(ty, out),
+            SqlParam::Bytea(v) => v.to_sql(ty, out),
+            SqlParam::Uuid(v) => {
+                postgres_protocol::types::uuid_to_sql(*v.as_bytes(), out);
+                Ok(IsNull::No)
+            }
+            SqlParam::Date(v) => v.to_sql(ty, out),
+            SqlParam::Time(v) => v.to_sql(ty, out),
+            SqlParam::Timestamp(v) => v.to_sql(ty, out),
+            SqlParam::TimestampTz(v) => v.to_sql(ty, out),
+            SqlParam::Array {
+                dimensions,
+                elements,
+            } => {
+                let Kind::Array(member) = ty.kind() else {
+                    return Err(format!("cannot bind an array to {}", type_name(ty)).into());
+                };
+                let dimensions = dimensions.iter().map(|&len| ArrayDimension {
+                    len,
+                    lower_bound: 1,
+                });
+                array_to_sql(
+                    dimensions,
+                    member.oid(),
+                    elements,
+                    |element, buf| match element.to_sql(member, buf)? {
+                        IsNull::No => Ok(postgres_protocol::IsNull::No),
+                        IsNull::Yes => Ok(postgres_protocol::IsNull::Yes),
+                    },
+                    out,
+                )?;
+                Ok(IsNull::No)
+            }
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        // Values are coerced against the statement's parameter types up front.
+        true
+    }
+
+    to_sql_checked!();
+}
+
+fn mismatch(value: &Value) -> String {
+    let shown = value.to_string();
+    if shown.chars().count() > 64 {
+        format!(
+            "cannot convert {}…",
+            shown.chars().take(64).collect::<String>()
+        )
+    } else {
+        format!("cannot convert {}", shown)
+    }
+}
+
+fn int_from_json<T: TryFrom<i64>>(value: &Value) -> Result<T, String> {
+    let n = match value {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| mismatch(value))?;
+    T::try_from(n).map_err(|_| format!("{} is out of range", n))
+}
+
+fn float_from_json(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| mismatch(value))
+}
+
+/// Decode `\x`-prefixed hex strings or arrays of byte values.
+fn bytea_from_json(value: &Value) -> Result<Vec<u8>, String> {
+    match value {
+        Value::String(s) => {
+            let hex = s
+                .strip_prefix("\\x")
+                .ok_or("expected a \\x-prefixed hex string or an array of bytes")?;
+            if !hex.len().is_multiple_of(2) {
+                return Err("hex string has an odd number of digits".into());
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| {
+                    u8::from_str_radix(hex.get(i..i + 2).unwrap_or("?"), 16)
+                        .map_err(|_| format!("invalid hex digits at offset {}", i))
+                })
+                .collect()
+        }
+        Value::Array(items) => items
+            .iter()
+            .map(|b| {
+                b.as_u64()
+                    .and_then(|b| u8::try_from(b).ok())
+                    .ok_or_else(|| mismatch(b))
+            })
+            .collect(),
+        _ => Err(mismatch(value)),
+    }
+}
+
+fn parse_str<T>(value: &Value, parse: impl Fn(&str) -> Option<T>) -> Result<T, String> {
+    value
+        .as_str()
+        .and_then(|s| parse(s.trim()))
+        .ok_or_else(|| mismatch(value))
+}
+
+fn parse_naive_datetime(s: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))
+        .ok()
+}
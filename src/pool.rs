@@ -0,0 +1,219 @@
+//! Connection pool sizing and recycling, and startup settings for each connection.
+
+use anyhow::{Context, Result};
+use deadpool_postgres::{ManagerConfig, PoolConfig, RecyclingMethod, Timeouts};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::hosts::{LoadBalanceHosts, TargetSessionAttrs};
+
+/// How a connection is checked before it is handed out again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Recycling {
+    /// Only check that the connection isn't closed.
+    #[default]
+    Fast,
+    /// Also run a test query.
+    Verified,
+    /// Also reset session state (`RESET ALL`, `DISCARD TEMP`, ...).
+    Clean,
+}
+
+impl Recycling {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Recycling::Fast => "fast",
+            Recycling::Verified => "verified",
+            Recycling::Clean => "clean",
+        }
+    }
+
+    fn method(&self) -> RecyclingMethod {
+        match self {
+            Recycling::Fast => RecyclingMethod::Fast,
+            Recycling::Verified => RecyclingMethod::Verified,
+            Recycling::Clean => RecyclingMethod::Clean,
+        }
+    }
+}
+
+impl FromStr for Recycling {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fast" => Ok(Recycling::Fast),
+            "verified" => Ok(Recycling::Verified),
+            "clean" => Ok(Recycling::Clean),
+            _ => anyhow::bail!("expected fast, verified or clean"),
+        }
+    }
+}
+
+/// Pool size, timeouts and recycling; unset fields keep the pool's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolSettings {
+    /// Most connections open at once (default: 4 per CPU).
+    pub max_size: Option<usize>,
+    /// How long a request waits for a free connection (default: no limit).
+    pub wait_timeout_ms: Option<u64>,
+    /// How long opening a new connection may take (default: no limit).
+    pub create_timeout_ms: Option<u64>,
+    /// How long checking a returned connection may take (default: no limit).
+    pub recycle_timeout_ms: Option<u64>,
+    pub recycling: Option<Recycling>,
+}
+
+impl PoolSettings {
+    /// Read the `pool_*` query parameters of a connection URL.
+    pub fn from_query(query_param: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        Ok(Self {
+            max_size: parse_param(&query_param, "pool_max_size")?,
+            wait_timeout_ms: parse_param(&query_param, "pool_wait_timeout_ms")?,
+            create_timeout_ms: parse_param(&query_param, "pool_create_timeout_ms")?,
+            recycle_timeout_ms: parse_param(&query_param, "pool_recycle_timeout_ms")?,
+            recycling: parse_param(&query_param, "pool_recycling")?,
+        })
+    }
+
+    /// Take every setting `other` has.
+    pub fn merge(&mut self, other: &PoolSettings) {
+        self.max_size = other.max_size.or(self.max_size);
+        self.wait_timeout_ms = other.wait_timeout_ms.or(self.wait_timeout_ms);
+        self.create_timeout_ms = other.create_timeout_ms.or(self.create_timeout_ms);
+        self.recycle_timeout_ms = other.recycle_timeout_ms.or(self.recycle_timeout_ms);
+        self.recycling = other.recycling.or(self.recycling);
+    }
+
+    pub fn pool_config(&self) -> Result<PoolConfig> {
+        let mut config = PoolConfig::default();
+        if let Some(max_size) = self.max_size {
+            if max_size == 0 {
+                anyhow::bail!("Pool max_size must be at least 1");
+            }
+            config.max_size = max_size;
+        }
+        config.timeouts = Timeouts {
+            wait: self.wait_timeout_ms.map(Duration::from_millis),
+            create: self.create_timeout_ms.map(Duration::from_millis),
+            recycle: self.recycle_timeout_ms.map(Duration::from_millis),
+        };
+        Ok(config)
+    }
+
+    pub fn manager_config(&self) -> ManagerConfig {
+        ManagerConfig {
+            recycling_method: self.recycling.unwrap_or_default().method(),
+        }
+    }
+
+    /// The settings that are set, by URL query parameter.
+    pub fn params(&self) -> Vec<(String, Value)> {
+        set_fields(self, "pool_")
+    }
+
+    /// The settings in effect, with defaults filled in from the pool's `max_size`.
+    pub fn resolved(&self, max_size: usize) -> Value {
+        json!({
+            "max_size": max_size,
+            "wait_timeout_ms": self.wait_timeout_ms,
+            "create_timeout_ms": self.create_timeout_ms,
+            "recycle_timeout_ms": self.recycle_timeout_ms,
+            "recycling": self.recycling.unwrap_or_default().as_str(),
+        })
+    }
+}
+
+/// Startup settings for each connection, named as in libpq.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ConnectSettings {
+    /// Seconds to wait while connecting (default: no limit).
+    pub connect_timeout: Option<u64>,
+    /// TCP keepalives (default: on).
+    pub keepalives: Option<bool>,
+    /// Seconds of inactivity before a keepalive is sent (default: 7200).
+    pub keepalives_idle: Option<u64>,
+    /// Shown in `pg_stat_activity` and server logs.
+    pub application_name: Option<String>,
+    /// Command-line options sent to the server, e.g. `-c statement_timeout=30s`.
+    pub options: Option<String>,
+    /// Which of several hosts to use (default: any).
+    pub target_session_attrs: Option<TargetSessionAttrs>,
+    /// Try hosts in random order instead of the order given.
+    pub load_balance_hosts: Option<LoadBalanceHosts>,
+}
+
+impl ConnectSettings {
+    /// Read these settings from the query parameters of a connection URL.
+    pub fn from_query(query_param: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        Ok(Self {
+            connect_timeout: parse_param(&query_param, "connect_timeout")?,
+            keepalives: query_param("keepalives")
+                .map(|v| parse_bool(&v).with_context(|| format!("Invalid keepalives '{}'", v)))
+                .transpose()?,
+            keepalives_idle: parse_param(&query_param, "keepalives_idle")?,
+            application_name: query_param("application_name"),
+            options: query_param("options"),
+            target_session_attrs: parse_param(&query_param, "target_session_attrs")?,
+            load_balance_hosts: parse_param(&query_param, "load_balance_hosts")?,
+        })
+    }
+
+    /// Take every setting `other` has.
+    pub fn merge(&mut self, other: &ConnectSettings) {
+        self.connect_timeout = other.connect_timeout.or(self.connect_timeout);
+        self.keepalives = other.keepalives.or(self.keepalives);
+        self.keepalives_idle = other.keepalives_idle.or(self.keepalives_idle);
+        if other.application_name.is_some() {
+            self.application_name = other.application_name.clone();
+        }
+        if other.options.is_some() {
+            self.options = other.options.clone();
+        }
+        self.target_session_attrs = other.target_session_attrs.or(self.target_session_attrs);
+        self.load_balance_hosts = other.load_balance_hosts.or(self.load_balance_hosts);
+    }
+
+    /// The settings that are set, by URL query parameter.
+    pub fn params(&self) -> Vec<(String, Value)> {
+        set_fields(self, "")
+    }
+}
+
+/// The fields of `settings` that aren't `None`, each name prefixed with `prefix`.
+fn set_fields(settings: &impl Serialize, prefix: &str) -> Vec<(String, Value)> {
+    match serde_json::to_value(settings) {
+        Ok(Value::Object(fields)) => fields
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(name, value)| (format!("{}{}", prefix, name), value))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// libpq booleans: `1`/`0`, `true`/`false`, `on`/`off`, `yes`/`no`.
+fn parse_bool(s: &str) -> Result<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "1" | "true" | "on" | "yes" => Ok(true),
+        "0" | "false" | "off" | "no" => Ok(false),
+        _ => anyhow::bail!("expected 1/0, true/false, on/off or yes/no"),
+    }
+}
+
+fn parse_param<T>(query_param: impl Fn(&str) -> Option<String>, key: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    query_param(key)
+        .map(|v| {
+            v.parse()
+                .map_err(|e| anyhow::anyhow!("Invalid {} '{}': {}", key, v, e))
+        })
+        .transpose()
+}
@@ -0,0 +1,160 @@
+//! Noticing when the database can't be reached, and reconnecting with backoff.
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Wait before the first reconnection attempt; doubled after each failure.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Longest wait between reconnection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A period when the database couldn't be reached.
+struct Outage {
+    since: Instant,
+    error: String,
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+/// Whether the database is reachable.
+#[derive(Default)]
+pub struct Availability {
+    outage: Mutex<Option<Outage>>,
+    lost: Notify,
+}
+
+impl Availability {
+    /// Fail fast while the database is unreachable, instead of waiting to connect.
+    pub fn check(&self) -> Result<()> {
+        if let Some(outage) = &*self.outage.lock().unwrap() {
+            anyhow::bail!(
+                "Database unavailable: {} (reconnecting, next attempt in {} ms)",
+                outage.error,
+                outage
+                    .next_attempt
+                    .saturating_duration_since(Instant::now())
+                    .as_millis()
+            );
+        }
+        Ok(())
+    }
+
+    /// Record that the database couldn't be reached, starting reconnection.
+    pub fn lost(&self, error: String) {
+        let mut outage = self.outage.lock().unwrap();
+        if outage.is_some() {
+            return;
+        }
+        tracing::warn!("Database unavailable, reconnecting: {}", error);
+        let now = Instant::now();
+        *outage = Some(Outage {
+            since: now,
+            error,
+            attempts: 0,
+            next_attempt: now + INITIAL_BACKOFF,
+        });
+        self.lost.notify_one();
+    }
+
+    /// The current outage, or `None` while the database is reachable.
+    pub fn outage(&self) -> Option<Value> {
+        self.outage.lock().unwrap().as_ref().map(|outage| {
+            json!({
+                "error": outage.error,
+                "down_for_ms": outage.since.elapsed().as_millis() as u64,
+                "attempts": outage.attempts,
+                "next_attempt_in_ms": outage
+                    .next_attempt
+                    .saturating_duration_since(Instant::now())
+                    .as_millis() as u64,
+            })
+        })
+    }
+
+    /// Each time the database is lost, retry `connect` with exponential backoff
+    /// until it succeeds. Runs forever.
+    pub async fn reconnect<F, Fut>(&self, connect: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        loop {
+            self.lost.notified().await;
+            let mut backoff = INITIAL_BACKOFF;
+            loop {
+                tokio::time::sleep(backoff).await;
+                let result = connect().await;
+                let mut outage = self.outage.lock().unwrap();
+                let Some(current) = outage.as_mut() else {
+                    break;
+                };
+                match result {
+                    Ok(()) => {
+                        tracing::info!(
+                            "Database reachable again after {} attempt(s)",
+                            current.attempts + 1
+                        );
+                        *outage = None;
+                        break;
+                    }
+                    Err(e) => {
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                        current.error = format!("{:#}", e);
+                        current.attempts += 1;
+                        current.next_attempt = Instant::now() + backoff;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::pin;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn reconnects_with_backoff() {
+        let availability = Availability::default();
+        availability.check().unwrap();
+        availability.lost("refused".to_string());
+        let e = availability.check().unwrap_err();
+        assert!(e.to_string().starts_with("Database unavailable: refused"));
+
+        let calls = &AtomicU32::new(0);
+        let most_attempts = &AtomicU32::new(0);
+        let started = Instant::now();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            // Down for two attempts
+            let reconnect = availability.reconnect(move || async move {
+                if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                    anyhow::bail!("still refused");
+                }
+                Ok(())
+            });
+            let recovered = async {
+                while let Some(outage) = availability.outage() {
+                    let attempts = outage["attempts"].as_u64().unwrap() as u32;
+                    most_attempts.fetch_max(attempts, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            };
+            futures_util::future::select(pin!(reconnect), pin!(recovered)).await;
+        });
+
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(most_attempts.load(Ordering::SeqCst), 2);
+        // 250 ms, then 500 ms, then 1000 ms
+        assert!(started.elapsed() >= INITIAL_BACKOFF * 7);
+        assert!(availability.outage().is_none());
+        availability.check().unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+//! Read replicas, and choosing the server that handles a request.
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::client::{ConnectionConfig, PostgresClient};
+
+/// Most replay lag a replica may have and still serve reads, unless configured.
+pub const DEFAULT_MAX_LAG: Duration = Duration::from_secs(5);
+
+/// How often each replica's replay lag is measured.
+pub const LAG_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A standby that can serve reads while it keeps up with the primary.
+pub struct Replica {
+    client: Arc<PostgresClient>,
+    max_lag: Duration,
+    /// Replay lag or why it couldn't be measured, and when.
+    last_check: Mutex<Option<(Result<Duration, String>, Instant)>>,
+}
+
+impl Replica {
+    pub async fn new(config: ConnectionConfig, max_lag: Duration) -> Result<Self> {
+        Ok(Self {
+            client: Arc::new(PostgresClient::new(config).await?),
+            max_lag,
+            last_check: Mutex::new(None),
+        })
+    }
+
+    pub fn client(&self) -> &Arc<PostgresClient> {
+        &self.client
+    }
+
+    /// Measure the replay lag.
+    pub async fn check(&self) {
+        // e.g. while cursors hold every connection of the pool
+        let lag = match tokio::time::timeout(LAG_CHECK_INTERVAL, self.client.replay_lag()).await {
+            Ok(lag) => lag.map_err(|e| format!("{:#}", e)),
+            Err(_) => Err("Timed out measuring replay lag".to_string()),
+        };
+        if let Err(e) = &lag {
+            tracing::debug!("Replica {} unusable: {}", self.client.connection_info(), e);
+        }
+        *self.last_check.lock().unwrap() = Some((lag, Instant::now()));
+    }
+
+    /// The replay lag, if the replica can serve reads.
+    ///
+    /// A measurement older than a few checks doesn't count, e.g. while a check hangs.
+    fn usable_lag(&self) -> Option<Duration> {
+        let last_check = self.last_check.lock().unwrap();
+        let (Ok(lag), at) = last_check.as_ref()? else {
+            return None;
+        };
+        let fresh = at.elapsed() < LAG_CHECK_INTERVAL * 3;
+        (fresh && *lag <= self.max_lag).then_some(*lag)
+    }
+
+    pub fn status(&self) -> Value {
+        let server = self.client.server();
+        let last_check = self.last_check.lock().unwrap();
+        let (lag_ms, error) = match &*last_check {
+            Some((Ok(lag), _)) => (Some(lag.as_millis() as u64), None),
+            Some((Err(e), _)) => (None, Some(e.clone())),
+            None => (None, Some("Not checked yet".to_string())),
+        };
+        drop(last_check);
+        json!({
+            "host": server.host,
+            "port": server.port,
+            "lag_ms": lag_ms,
+            "max_lag_ms": self.max_lag.as_millis() as u64,
+            "usable": self.usable_lag().is_some(),
+            "error": error,
+        })
+    }
+}
+
+/// The primary of a connection and its replicas.
+pub struct Servers {
+    pub primary: Arc<PostgresClient>,
+    pub replicas: Vec<Replica>,
+}
+
+impl Servers {
+    /// The primary, for writes and anything stateful.
+    pub fn primary(&self) -> Route {
+        Route {
+            client: self.primary.clone(),
+            replica: false,
+        }
+    }
+
+    /// The least lagging usable replica for a read, or the primary if there is none.
+    pub fn reader(&self) -> Route {
+        self.replicas
+            .iter()
+            .filter_map(|replica| replica.usable_lag().map(|lag| (lag, replica)))
+            .min_by_key(|(lag, _)| *lag)
+            .map(|(_, replica)| Route {
+                client: replica.client.clone(),
+                replica: true,
+            })
+            .unwrap_or_else(|| self.primary())
+    }
+
+    /// The server holding state that `owns` finds, e.g. a cursor; the primary if none does.
+    pub fn owner(&self, owns: impl Fn(&PostgresClient) -> bool) -> Route {
+        self.replicas
+            .iter()
+            .find(|replica| owns(&replica.client))
+            .map(|replica| Route {
+                client: replica.client.clone(),
+                replica: true,
+            })
+            .unwrap_or_else(|| self.primary())
+    }
+
+    /// Every server's client, primary first.
+    pub fn clients(&self) -> impl Iterator<Item = &Arc<PostgresClient>> {
+        std::iter::once(&self.primary).chain(self.replicas.iter().map(Replica::client))
+    }
+}
+
+/// The server chosen to handle a request.
+pub struct Route {
+    pub client: Arc<PostgresClient>,
+    pub replica: bool,
+}
+
+impl Route {
+    /// Which server handled the request, for its response.
+    pub fn server(&self) -> Value {
+        let server = self.client.server();
+        json!({
+            "role": if self.replica { "replica" } else { "primary" },
+            "host": server.host,
+            "port": server.port,
+        })
+    }
+}
@@ -6,29 +6,74 @@ use fgp_daemon::FgpService;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::runtime::Runtime;
 
-use crate::client::{ConnectionConfig, PostgresClient};
+use crate::cancel::Limits;
+use crate::client::{
+    parse_isolation, ExecuteOptions, PostgresClient, QueryOptions, RetryPolicy, TransactionOptions,
+    TxStatement,
+};
+use crate::connections::Connections;
+use crate::copy::{self, quote_ident, ColumnMapping, ExportOptions, ImportOptions};
+use crate::error;
+use crate::replica::{Replica, Route};
+use crate::session::{DEFAULT_SESSION_IDLE_TIMEOUT, MAX_SESSION_IDLE_TIMEOUT};
+
+/// How often idle cursors, sessions and pools are checked for expiry.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
 
 /// FGP service for PostgreSQL operations.
 pub struct PostgresService {
-    client: Arc<PostgresClient>,
+    connections: Arc<Connections>,
     runtime: Runtime,
-    config: ConnectionConfig,
 }
 
 impl PostgresService {
-    /// Create a new PostgresService with the given connection config.
-    pub fn new(config: ConnectionConfig) -> Result<Self> {
+    /// Create a new PostgresService serving the given connections.
+    pub fn new(connections: Connections) -> Result<Self> {
         let runtime = Runtime::new()?;
-
-        // Create client inside runtime context
-        let client = runtime.block_on(async { PostgresClient::new(config.clone()).await })?;
+        let connections = Arc::new(connections);
+
+        // Release cursors, sessions and pools that callers abandoned
+        let sweeper = connections.clone();
+        runtime.spawn(async move {
+            let mut tick = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                tick.tick().await;
+                sweeper.sweep().await;
+            }
+        });
 
         Ok(Self {
-            client: Arc::new(client),
+            connections,
             runtime,
-            config,
+        })
+    }
+
+    /// The server of the `connection` parameter's connection (or the default one)
+    /// that handles `method`.
+    ///
+    /// Reads outside a session go to a replica when one is caught up, and cursors
+    /// and running requests are found where they were started.
+    fn route(&self, method: &str, params: &HashMap<String, Value>) -> Result<Route> {
+        let name = Self::get_str(params, "connection");
+        let servers = self.runtime.block_on(self.connections.get(name))?;
+        Ok(match method {
+            "query" | "postgres.query" if Self::get_str(params, "session_id").is_none() => {
+                servers.reader()
+            }
+            "tables" | "postgres.tables" | "schema" | "postgres.schema" | "schemas"
+            | "postgres.schemas" | "stats" | "postgres.stats" => servers.reader(),
+            "fetch" | "postgres.fetch" | "close_cursor" | "postgres.close_cursor" => {
+                let cursor_id = Self::get_str(params, "cursor_id").unwrap_or_default();
+                servers.owner(|client| client.has_cursor(cursor_id))
+            }
+            "cancel" | "postgres.cancel" => {
+                let request_id = Self::get_str(params, "request_id").unwrap_or_default();
+                servers.owner(|client| client.is_running(request_id))
+            }
+            _ => servers.primary(),
         })
     }
 
@@ -49,99 +94,426 @@ impl PostgresService {
             .unwrap_or(default)
     }
 
+    /// Helper to get an optional boolean parameter.
+    fn get_bool(params: &HashMap<String, Value>, key: &str) -> Result<bool> {
+        Self::get_bool_default(params, key, false)
+    }
+
+    /// Helper to get boolean parameter with default.
+    fn get_bool_default(params: &HashMap<String, Value>, key: &str, default: bool) -> Result<bool> {
+        Ok(Self::get_opt_bool(params, key)?.unwrap_or(default))
+    }
+
+    /// Helper to get an optional boolean parameter, keeping "unset" distinct.
+    fn get_opt_bool(params: &HashMap<String, Value>, key: &str) -> Result<Option<bool>> {
+        match params.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::Bool(b)) => Ok(Some(*b)),
+            Some(_) => anyhow::bail!("Parameter {} must be a boolean", key),
+        }
+    }
+
+    /// Helper to get an optional row count, which must be at least 1.
+    fn get_count(params: &HashMap<String, Value>, key: &str) -> Result<Option<i64>> {
+        match params.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(v) => match v.as_i64() {
+                Some(n) if n >= 1 => Ok(Some(n)),
+                _ => anyhow::bail!("Parameter {} must be a positive integer", key),
+            },
+        }
+    }
+
+    /// Helper to get the optional positional `params` array.
+    fn get_sql_params(params: &HashMap<String, Value>) -> Result<Vec<Value>> {
+        match params.get("params") {
+            None | Some(Value::Null) => Ok(Vec::new()),
+            Some(Value::Array(values)) => Ok(values.clone()),
+            Some(_) => anyhow::bail!("Parameter params must be an array"),
+        }
+    }
+
+    /// Helper to get `timeout_ms`, `lock_timeout_ms` and `request_id`.
+    fn get_limits(params: &HashMap<String, Value>) -> Result<Limits> {
+        let millis = |key| {
+            Ok::<_, anyhow::Error>(
+                Self::get_count(params, key)?.map(|ms| Duration::from_millis(ms as u64)),
+            )
+        };
+        Ok(Limits {
+            timeout: millis("timeout_ms")?,
+            lock_timeout: millis("lock_timeout_ms")?,
+            request_id: Self::get_str(params, "request_id").map(str::to_string),
+        })
+    }
+
     /// Health check implementation.
-    fn health(&self) -> Result<Value> {
-        let client = self.client.clone();
-        let ok = self.runtime.block_on(async move { client.ping().await })?;
+    fn health(&self, params: HashMap<String, Value>) -> Result<Value> {
+        let servers = self
+            .runtime
+            .block_on(self.connections.get(Self::get_str(&params, "connection")))?;
+        let client = servers.primary.clone();
+        let ok = match self.runtime.block_on(client.ping()) {
+            Ok(ok) => ok,
+            // Degraded while reconnecting; the outage says why
+            Err(_) if client.outage().is_some() => false,
+            Err(e) => return Err(e),
+        };
+        let outage = client.outage();
+        let config = client.config();
+        let server = client.server();
+        let status = match (&outage, ok) {
+            (Some(_), _) => "degraded",
+            (None, true) => "healthy",
+            (None, false) => "unhealthy",
+        };
 
         Ok(serde_json::json!({
-            "status": if ok { "healthy" } else { "unhealthy" },
-            "database": self.config.database,
-            "host": self.config.host,
-            "port": self.config.port,
+            "status": status,
+            "outage": outage,
+            "connection": Self::get_str(&params, "connection")
+                .unwrap_or(self.connections.default_name()),
+            "database": config.database,
+            "host": server.host,
+            "port": server.port,
+            "hosts": config.hosts()?.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "target_session_attrs": config.connect.target_session_attrs.unwrap_or_default(),
+            "read_only": config.read_only,
+            "guard": config.guard,
+            "pool": client.pool_status(),
+            "replicas": servers.replicas.iter().map(Replica::status).collect::<Vec<_>>(),
             "version": env!("CARGO_PKG_VERSION"),
         }))
     }
 
     /// Execute SQL query.
-    fn query(&self, params: HashMap<String, Value>) -> Result<Value> {
+    fn query(&self, client: Arc<PostgresClient>, params: HashMap<String, Value>) -> Result<Value> {
         let sql = Self::get_str(&params, "sql")
             .ok_or_else(|| anyhow::anyhow!("Missing required parameter: sql"))?
             .to_string();
+        let sql_params = Self::get_sql_params(&params)?;
+        let options = QueryOptions {
+            column_metadata: Self::get_bool(&params, "column_metadata")?,
+            row_format: Self::get_str_default(&params, "row_format", "objects").parse()?,
+            max_rows: Self::get_count(&params, "max_rows")?,
+            cursor: Self::get_bool(&params, "cursor")?,
+            limits: Self::get_limits(&params)?,
+        };
+        let session_id = Self::get_str(&params, "session_id").map(str::to_string);
+
+        self.runtime.block_on(async move {
+            match session_id {
+                Some(id) => client.session_query(&id, &sql, &sql_params, &options).await,
+                None => client.query(&sql, &sql_params, &options).await,
+            }
+        })
+    }
+
+    /// Fetch more rows from a cursor.
+    fn fetch(&self, client: Arc<PostgresClient>, params: HashMap<String, Value>) -> Result<Value> {
+        let cursor_id = Self::get_str(&params, "cursor_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: cursor_id"))?
+            .to_string();
+        let count = Self::get_count(&params, "count")?;
 
-        let client = self.client.clone();
+        self.runtime
+            .block_on(async move { client.fetch(&cursor_id, count).await })
+    }
+
+    /// Close a cursor.
+    fn close_cursor(
+        &self,
+        client: Arc<PostgresClient>,
+        params: HashMap<String, Value>,
+    ) -> Result<Value> {
+        let cursor_id = Self::get_str(&params, "cursor_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: cursor_id"))?
+            .to_string();
 
         self.runtime
-            .block_on(async move { client.query(&sql, &[]).await })
+            .block_on(async move { client.close_cursor(&cursor_id).await })
     }
 
     /// Execute non-SELECT statement.
-    fn execute(&self, params: HashMap<String, Value>) -> Result<Value> {
+    fn execute(
+        &self,
+        client: Arc<PostgresClient>,
+        params: HashMap<String, Value>,
+    ) -> Result<Value> {
         let sql = Self::get_str(&params, "sql")
             .ok_or_else(|| anyhow::anyhow!("Missing required parameter: sql"))?
             .to_string();
+        let sql_params = Self::get_sql_params(&params)?;
+        let options = ExecuteOptions {
+            returning: Self::get_bool(&params, "returning")?,
+            dry_run: Self::get_bool(&params, "dry_run")?,
+            limits: Self::get_limits(&params)?,
+        };
+        let session_id = Self::get_str(&params, "session_id").map(str::to_string);
+        let confirm = Self::get_str(&params, "confirm").map(str::to_string);
 
-        let client = self.client.clone();
+        self.runtime.block_on(async move {
+            // Dry runs refuse transaction control, so they always roll back
+            if !options.dry_run {
+                let statement = TxStatement {
+                    sql: sql.clone(),
+                    params: sql_params.clone(),
+                    returning: options.returning,
+                };
+                client
+                    .require_confirmation(&[statement], confirm.as_deref())
+                    .await?;
+            }
+            match session_id {
+                Some(id) => {
+                    client
+                        .session_execute(&id, &sql, &sql_params, &options)
+                        .await
+                }
+                None => client.execute(&sql, &sql_params, &options).await,
+            }
+        })
+    }
+
+    /// Start an interactive transaction session.
+    fn begin(&self, client: Arc<PostgresClient>, params: HashMap<String, Value>) -> Result<Value> {
+        let options = Self::get_transaction_options(&params)?;
+        let idle_timeout = match Self::get_count(&params, "idle_timeout_ms")? {
+            Some(ms) => Duration::from_millis(ms as u64),
+            None => DEFAULT_SESSION_IDLE_TIMEOUT,
+        };
+        if idle_timeout > MAX_SESSION_IDLE_TIMEOUT {
+            anyhow::bail!(
+                "idle_timeout_ms must be at most {}",
+                MAX_SESSION_IDLE_TIMEOUT.as_millis()
+            );
+        }
+
+        self.runtime
+            .block_on(async move { client.begin(&options, idle_timeout).await })
+    }
+
+    /// Helper to get the required session_id parameter.
+    fn get_session_id(params: &HashMap<String, Value>) -> Result<String> {
+        Self::get_str(params, "session_id")
+            .map(str::to_string)
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: session_id"))
+    }
+
+    /// Commit a session.
+    fn commit(&self, client: Arc<PostgresClient>, params: HashMap<String, Value>) -> Result<Value> {
+        let session_id = Self::get_session_id(&params)?;
+
+        self.runtime
+            .block_on(async move { client.commit(&session_id).await })
+    }
+
+    /// Roll back a session, or to one of its savepoints.
+    fn rollback(
+        &self,
+        client: Arc<PostgresClient>,
+        params: HashMap<String, Value>,
+    ) -> Result<Value> {
+        let session_id = Self::get_session_id(&params)?;
+        let savepoint = Self::get_str(&params, "savepoint").map(str::to_string);
 
         self.runtime
-            .block_on(async move { client.execute(&sql, &[]).await })
+            .block_on(async move { client.rollback(&session_id, savepoint.as_deref()).await })
+    }
+
+    /// Set or release a savepoint in a session.
+    fn savepoint(
+        &self,
+        client: Arc<PostgresClient>,
+        params: HashMap<String, Value>,
+        release: bool,
+    ) -> Result<Value> {
+        let session_id = Self::get_session_id(&params)?;
+        let name = Self::get_str(&params, "name")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: name"))?
+            .to_string();
+
+        self.runtime.block_on(async move {
+            if release {
+                client.release_savepoint(&session_id, &name).await
+            } else {
+                client.savepoint(&session_id, &name).await
+            }
+        })
+    }
+
+    /// Helper to get isolation, read_only and deferrable.
+    fn get_transaction_options(params: &HashMap<String, Value>) -> Result<TransactionOptions> {
+        Ok(TransactionOptions {
+            isolation: Self::get_str(params, "isolation")
+                .map(parse_isolation)
+                .transpose()?,
+            read_only: Self::get_opt_bool(params, "read_only")?,
+            deferrable: Self::get_opt_bool(params, "deferrable")?,
+            retry: None,
+            dry_run: false,
+            limits: Limits::default(),
+        })
     }
 
     /// Execute transaction.
-    fn transaction(&self, params: HashMap<String, Value>) -> Result<Value> {
-        let statements: Vec<String> = params
+    fn transaction(
+        &self,
+        client: Arc<PostgresClient>,
+        params: HashMap<String, Value>,
+    ) -> Result<Value> {
+        let statements: Vec<TxStatement> = params
             .get("statements")
             .and_then(|v| v.as_array())
-            .map(|arr| {
-                arr.iter()
-                    .filter_map(|v| v.as_str())
-                    .map(|s| s.to_string())
-                    .collect()
-            })
-            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: statements (array)"))?;
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: statements (array)"))?
+            .iter()
+            .map(TxStatement::from_json)
+            .collect::<Result<_>>()?;
 
         if statements.is_empty() {
             anyhow::bail!("statements array cannot be empty");
         }
 
-        let client = self.client.clone();
+        let options = TransactionOptions {
+            retry: params
+                .get("retry")
+                .map(RetryPolicy::from_json)
+                .transpose()?
+                .flatten(),
+            dry_run: Self::get_bool(&params, "dry_run")?,
+            limits: Self::get_limits(&params)?,
+            ..Self::get_transaction_options(&params)?
+        };
+        let confirm = Self::get_str(&params, "confirm").map(str::to_string);
+
+        self.runtime.block_on(async move {
+            // Dry runs refuse transaction control, so they always roll back
+            if !options.dry_run {
+                client
+                    .require_confirmation(&statements, confirm.as_deref())
+                    .await?;
+            }
+            client.transaction(&statements, &options).await
+        })
+    }
+
+    /// Cancel a running request by its request_id.
+    fn cancel(&self, client: Arc<PostgresClient>, params: HashMap<String, Value>) -> Result<Value> {
+        let request_id = Self::get_str(&params, "request_id")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: request_id"))?;
+
+        client.cancel(request_id)
+    }
+
+    /// Export a query or table to a file.
+    fn export(&self, client: Arc<PostgresClient>, params: HashMap<String, Value>) -> Result<Value> {
+        let path = Self::get_str(&params, "path")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: path"))?
+            .to_string();
+        let sql = match (
+            Self::get_str(&params, "sql"),
+            Self::get_str(&params, "table"),
+        ) {
+            (Some(sql), None) => sql.to_string(),
+            (None, Some(table)) => {
+                copy::table_query(Self::get_str_default(&params, "schema", "public"), table)
+            }
+            _ => anyhow::bail!("Exactly one of sql or table is required"),
+        };
+        let delimiter = match Self::get_str(&params, "delimiter") {
+            Some(d) if d.chars().count() == 1 => d.chars().next(),
+            Some(d) => anyhow::bail!("delimiter must be a single character, got {:?}", d),
+            None => None,
+        };
+        let options = ExportOptions {
+            format: Self::get_str_default(&params, "format", "csv").parse()?,
+            header: Self::get_bool_default(&params, "header", true)?,
+            delimiter,
+            overwrite: Self::get_bool(&params, "overwrite")?,
+        };
 
         self.runtime
-            .block_on(async move { client.transaction(&statements).await })
+            .block_on(async move { client.export(&sql, &path, &options).await })
     }
 
-    /// List tables.
-    fn tables(&self, params: HashMap<String, Value>) -> Result<Value> {
+    /// Import a file into a table.
+    fn import(&self, client: Arc<PostgresClient>, params: HashMap<String, Value>) -> Result<Value> {
+        let path = Self::get_str(&params, "path")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: path"))?
+            .to_string();
+        let table = Self::get_str(&params, "table")
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: table"))?
+            .to_string();
         let schema = Self::get_str_default(&params, "schema", "public").to_string();
-        let client = self.client.clone();
+        let delimiter = match Self::get_str(&params, "delimiter") {
+            Some(d) if d.chars().count() == 1 => d.chars().next(),
+            Some(d) => anyhow::bail!("delimiter must be a single character, got {:?}", d),
+            None => None,
+        };
+        let columns = match params.get("columns") {
+            None | Some(Value::Null) => None,
+            Some(value) => Some(ColumnMapping::from_json(value)?),
+        };
+        let conflict_columns = match params.get("conflict_columns") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(value) => serde_json::from_value(value.clone())
+                .map_err(|_| anyhow::anyhow!("conflict_columns must be an array of names"))?,
+        };
+        let options = ImportOptions {
+            format: Self::get_str_default(&params, "format", "csv").parse()?,
+            header: Self::get_bool_default(&params, "header", true)?,
+            delimiter,
+            columns,
+            on_conflict: Self::get_str_default(&params, "on_conflict", "error").parse()?,
+            conflict_columns,
+            transaction: Self::get_bool_default(&params, "transaction", true)?,
+            truncate: Self::get_bool(&params, "truncate")?,
+            dry_run: Self::get_bool(&params, "dry_run")?,
+        };
+        let confirm = Self::get_str(&params, "confirm").map(str::to_string);
 
+        self.runtime.block_on(async move {
+            if options.truncate && !options.dry_run {
+                // Emptying the table is guarded like the TRUNCATE it runs
+                let statement = TxStatement {
+                    sql: format!("TRUNCATE {}.{}", quote_ident(&schema), quote_ident(&table)),
+                    params: Vec::new(),
+                    returning: false,
+                };
+                client
+                    .require_confirmation(&[statement], confirm.as_deref())
+                    .await?;
+            }
+            client.import(&path, &schema, &table, &options).await
+        })
+    }
+
+    /// List tables.
+    fn tables(&self, client: Arc<PostgresClient>, params: HashMap<String, Value>) -> Result<Value> {
+        let schema = Self::get_str_default(&params, "schema", "public").to_string();
         self.runtime
             .block_on(async move { client.list_tables(&schema).await })
     }
 
     /// Get table schema.
-    fn schema(&self, params: HashMap<String, Value>) -> Result<Value> {
+    fn schema(&self, client: Arc<PostgresClient>, params: HashMap<String, Value>) -> Result<Value> {
         let table = Self::get_str(&params, "table")
             .ok_or_else(|| anyhow::anyhow!("Missing required parameter: table"))?
             .to_string();
         let schema = Self::get_str_default(&params, "schema", "public").to_string();
 
-        let client = self.client.clone();
-
         self.runtime
             .block_on(async move { client.table_schema(&table, &schema).await })
     }
 
     /// List schemas.
-    fn schemas(&self) -> Result<Value> {
-        let client = self.client.clone();
+    fn schemas(&self, client: Arc<PostgresClient>) -> Result<Value> {
         self.runtime
             .block_on(async move { client.list_schemas().await })
     }
 
     /// Get database stats.
-    fn stats(&self) -> Result<Value> {
-        let client = self.client.clone();
+    fn stats(&self, client: Arc<PostgresClient>) -> Result<Value> {
         self.runtime.block_on(async move { client.stats().await })
     }
 }
@@ -156,17 +528,44 @@ impl FgpService for PostgresService {
     }
 
     fn dispatch(&self, method: &str, params: HashMap<String, Value>) -> Result<Value> {
-        match method {
-            "health" => self.health(),
-            "query" | "postgres.query" => self.query(params),
-            "execute" | "postgres.execute" => self.execute(params),
-            "transaction" | "postgres.transaction" => self.transaction(params),
-            "tables" | "postgres.tables" => self.tables(params),
-            "schema" | "postgres.schema" => self.schema(params),
-            "schemas" | "postgres.schemas" => self.schemas(),
-            "stats" | "postgres.stats" => self.stats(),
-            _ => anyhow::bail!("Unknown method: {}", method),
-        }
+        let sql = Self::get_str(&params, "sql").map(str::to_string);
+        let result = match method {
+            "health" => self.health(params),
+            "connections" | "postgres.connections" => self.connections.list(),
+            _ => self.route(method, &params).and_then(|route| {
+                let client = route.client.clone();
+                let result = match method {
+                    "query" | "postgres.query" => self.query(client, params),
+                    "fetch" | "postgres.fetch" => self.fetch(client, params),
+                    "close_cursor" | "postgres.close_cursor" => self.close_cursor(client, params),
+                    "execute" | "postgres.execute" => self.execute(client, params),
+                    "transaction" | "postgres.transaction" => self.transaction(client, params),
+                    "cancel" | "postgres.cancel" => self.cancel(client, params),
+                    "begin" | "postgres.begin" => self.begin(client, params),
+                    "commit" | "postgres.commit" => self.commit(client, params),
+                    "rollback" | "postgres.rollback" => self.rollback(client, params),
+                    "savepoint" | "postgres.savepoint" => self.savepoint(client, params, false),
+                    "release_savepoint" | "postgres.release_savepoint" => {
+                        self.savepoint(client, params, true)
+                    }
+                    "export" | "postgres.export" => self.export(client, params),
+                    "import" | "postgres.import" => self.import(client, params),
+                    "tables" | "postgres.tables" => self.tables(client, params),
+                    "schema" | "postgres.schema" => self.schema(client, params),
+                    "schemas" | "postgres.schemas" => self.schemas(client),
+                    "stats" | "postgres.stats" => self.stats(client),
+                    _ => Err(anyhow::anyhow!("Unknown method: {}", method)),
+                };
+                // Say which server handled the request
+                result.map(|mut value| {
+                    if let Some(object) = value.as_object_mut() {
+                        object.insert("server".into(), route.server());
+                    }
+                    value
+                })
+            }),
+        };
+        result.map_err(|e| error::structured(e, sql.as_deref()))
     }
 
     fn method_list(&self) -> Vec<MethodInfo> {
@@ -175,35 +574,276 @@ impl FgpService for PostgresService {
                 .schema(serde_json::json!({
                     "type": "object",
                     "properties": {
-                        "sql": { "type": "string", "description": "SQL query to execute" }
+                        "sql": { "type": "string", "description": "SQL query to execute" },
+                        "params": {
+                            "type": "array",
+                            "description": "Positional parameters bound to $1, $2, ..."
+                        },
+                        "column_metadata": {
+                            "type": "boolean",
+                            "default": false,
+                            "description": "Describe each column (type, OID, modifier, nullability, source table) instead of listing names"
+                        },
+                        "row_format": {
+                            "type": "string",
+                            "enum": ["objects", "arrays"],
+                            "default": "objects",
+                            "description": "Rows as objects keyed by column name, or as positional arrays"
+                        },
+                        "max_rows": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Return at most this many rows (page size in cursor mode)"
+                        },
+                        "cursor": {
+                            "type": "boolean",
+                            "default": false,
+                            "description": "Return the first page and a cursor_id for postgres.fetch"
+                        },
+                        "timeout_ms": { "type": "integer", "description": "Cancel the request once it has run this long" },
+                        "lock_timeout_ms": { "type": "integer", "description": "How long each statement may wait for a lock (SET LOCAL lock_timeout)" },
+                        "request_id": { "type": "string", "description": "Name the request so postgres.cancel can cancel it (the FGP envelope id isn't visible to the service; without request_id it can't be cancelled)" },
+                        "session_id": { "type": "string", "description": "Run inside a session from postgres.begin" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
                     },
                     "required": ["sql"]
                 })),
+            MethodInfo::new("postgres.fetch", "Fetch the next rows from a cursor opened by postgres.query")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "cursor_id": { "type": "string", "description": "Cursor returned by postgres.query" },
+                        "count": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Rows to fetch (default: the cursor's page size)"
+                        },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    },
+                    "required": ["cursor_id"]
+                })),
+            MethodInfo::new("postgres.close_cursor", "Close a cursor and release its connection")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "cursor_id": { "type": "string", "description": "Cursor returned by postgres.query" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    },
+                    "required": ["cursor_id"]
+                })),
             MethodInfo::new("postgres.execute", "Execute a non-SELECT statement (INSERT, UPDATE, DELETE)")
                 .schema(serde_json::json!({
                     "type": "object",
                     "properties": {
-                        "sql": { "type": "string", "description": "SQL statement to execute" }
+                        "sql": { "type": "string", "description": "SQL statement to execute" },
+                        "params": {
+                            "type": "array",
+                            "description": "Positional parameters bound to $1, $2, ..."
+                        },
+                        "returning": {
+                            "type": "boolean",
+                            "default": false,
+                            "description": "Return the statement's rows (e.g. from RETURNING)"
+                        },
+                        "dry_run": {
+                            "type": "boolean",
+                            "default": false,
+                            "description": "Run with constraints checked immediately, report the result, then roll back"
+                        },
+                        "timeout_ms": { "type": "integer", "description": "Cancel the request once it has run this long" },
+                        "lock_timeout_ms": { "type": "integer", "description": "How long each statement may wait for a lock (SET LOCAL lock_timeout)" },
+                        "request_id": { "type": "string", "description": "Name the request so postgres.cancel can cancel it (the FGP envelope id isn't visible to the service; without request_id it can't be cancelled)" },
+                        "session_id": { "type": "string", "description": "Run inside a session from postgres.begin" },
+                        "confirm": { "type": "string", "description": "Confirmation token from a refused destructive statement on a guarded connection" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
                     },
                     "required": ["sql"]
                 })),
+            MethodInfo::new("postgres.begin", "Start an interactive transaction; pass the session_id to query/execute")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "isolation": {
+                            "type": "string",
+                            "enum": ["read committed", "repeatable read", "serializable"],
+                            "description": "Isolation level (default: the server's)"
+                        },
+                        "read_only": { "type": "boolean", "description": "Start a READ ONLY transaction" },
+                        "deferrable": { "type": "boolean", "description": "DEFERRABLE (with serializable, read_only)" },
+                        "idle_timeout_ms": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "maximum": 3600000,
+                            "default": 60000,
+                            "description": "Roll back the session after this long without a request"
+                        },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    }
+                })),
+            MethodInfo::new("postgres.commit", "Commit a session and release its connection")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": { "type": "string", "description": "Session returned by postgres.begin" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    },
+                    "required": ["session_id"]
+                })),
+            MethodInfo::new("postgres.rollback", "Roll back a session, or to a savepoint within it")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": { "type": "string", "description": "Session returned by postgres.begin" },
+                        "savepoint": { "type": "string", "description": "Roll back to this savepoint and keep the session open" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    },
+                    "required": ["session_id"]
+                })),
+            MethodInfo::new("postgres.savepoint", "Set a savepoint in a session")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": { "type": "string", "description": "Session returned by postgres.begin" },
+                        "name": { "type": "string", "description": "Savepoint name" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    },
+                    "required": ["session_id", "name"]
+                })),
+            MethodInfo::new("postgres.release_savepoint", "Release a savepoint, keeping its changes")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "session_id": { "type": "string", "description": "Session returned by postgres.begin" },
+                        "name": { "type": "string", "description": "Savepoint name" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    },
+                    "required": ["session_id", "name"]
+                })),
             MethodInfo::new("postgres.transaction", "Execute multiple statements in a transaction")
                 .schema(serde_json::json!({
                     "type": "object",
                     "properties": {
                         "statements": {
                             "type": "array",
-                            "items": { "type": "string" },
+                            "items": {
+                                "oneOf": [
+                                    { "type": "string" },
+                                    {
+                                        "type": "object",
+                                        "properties": {
+                                            "sql": { "type": "string" },
+                                            "params": { "type": "array", "description": "Positional parameters bound to $1, $2, ..." },
+                                            "returning": { "type": "boolean", "default": false, "description": "Return the statement's rows" }
+                                        },
+                                        "required": ["sql"]
+                                    }
+                                ]
+                            },
                             "description": "SQL statements to execute in transaction"
-                        }
+                        },
+                        "isolation": {
+                            "type": "string",
+                            "enum": ["read committed", "repeatable read", "serializable"],
+                            "description": "Isolation level (default: the server's)"
+                        },
+                        "read_only": { "type": "boolean", "description": "Start a READ ONLY transaction" },
+                        "deferrable": { "type": "boolean", "description": "DEFERRABLE (with serializable, read_only)" },
+                        "dry_run": {
+                            "type": "boolean",
+                            "default": false,
+                            "description": "Run with constraints checked immediately, report the results, then roll back"
+                        },
+                        "timeout_ms": { "type": "integer", "description": "Cancel the request once it has run this long" },
+                        "lock_timeout_ms": { "type": "integer", "description": "How long each statement may wait for a lock (SET LOCAL lock_timeout)" },
+                        "request_id": { "type": "string", "description": "Name the request so postgres.cancel can cancel it (the FGP envelope id isn't visible to the service; without request_id it can't be cancelled)" },
+                        "confirm": { "type": "string", "description": "Confirmation token from a refused destructive statement on a guarded connection" },
+                        "retry": {
+                            "description": "Replay the transaction on serialization failures/deadlocks: true for the defaults, or a policy",
+                            "oneOf": [
+                                { "type": "boolean" },
+                                {
+                                    "type": "object",
+                                    "properties": {
+                                        "max_attempts": { "type": "integer", "default": 3, "description": "Total attempts, including the first" },
+                                        "base_delay_ms": { "type": "integer", "default": 50, "description": "Backoff before the first retry (doubles per retry, jittered)" },
+                                        "max_delay_ms": { "type": "integer", "default": 2000, "description": "Maximum backoff" },
+                                        "sqlstates": { "type": "array", "items": { "type": "string" }, "default": ["40001", "40P01"], "description": "SQLSTATEs to retry" }
+                                    }
+                                }
+                            ]
+                        },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
                     },
                     "required": ["statements"]
                 })),
+            MethodInfo::new("postgres.cancel", "Cancel a running query, execute or transaction by its request_id")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "request_id": { "type": "string", "description": "request_id the request was sent with (not its FGP envelope id)" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    },
+                    "required": ["request_id"]
+                })),
+            MethodInfo::new("postgres.export", "Export a query or table to a file with COPY")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "File to write (on the daemon's host)" },
+                        "sql": { "type": "string", "description": "Query to export" },
+                        "table": { "type": "string", "description": "Table to export (instead of sql)" },
+                        "schema": { "type": "string", "default": "public", "description": "Schema of table" },
+                        "format": {
+                            "type": "string",
+                            "enum": ["csv", "tsv", "ndjson", "binary"],
+                            "default": "csv"
+                        },
+                        "header": { "type": "boolean", "default": true, "description": "Write column names first (csv, tsv)" },
+                        "delimiter": { "type": "string", "default": ",", "description": "CSV field delimiter" },
+                        "overwrite": { "type": "boolean", "default": false, "description": "Replace an existing file" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    },
+                    "required": ["path"]
+                })),
+            MethodInfo::new("postgres.import", "Load a CSV or NDJSON file into a table with COPY")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "File to read (on the daemon's host)" },
+                        "table": { "type": "string", "description": "Target table" },
+                        "schema": { "type": "string", "default": "public", "description": "Schema of table" },
+                        "format": { "type": "string", "enum": ["csv", "ndjson"], "default": "csv" },
+                        "header": { "type": "boolean", "default": true, "description": "The CSV file starts with a header line" },
+                        "delimiter": { "type": "string", "default": ",", "description": "CSV field delimiter" },
+                        "columns": {
+                            "type": ["array", "object"],
+                            "description": "Column names in file order (CSV) or keys to load (NDJSON), or an object mapping source fields to columns"
+                        },
+                        "on_conflict": {
+                            "type": "string",
+                            "enum": ["error", "skip", "update"],
+                            "default": "error",
+                            "description": "What to do with rows that violate a unique constraint"
+                        },
+                        "conflict_columns": {
+                            "type": "array",
+                            "items": { "type": "string" },
+                            "description": "Conflict target for on_conflict=update (default: primary key)"
+                        },
+                        "transaction": { "type": "boolean", "default": true, "description": "Run the whole import in one transaction" },
+                        "truncate": { "type": "boolean", "default": false, "description": "Empty the table before loading; needs confirm on a guarded connection" },
+                        "dry_run": { "type": "boolean", "default": false, "description": "Load and report, then roll back" },
+                        "confirm": { "type": "string", "description": "Confirmation token from a refused truncate on a guarded connection" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    },
+                    "required": ["path", "table"]
+                })),
             MethodInfo::new("postgres.tables", "List tables in a schema")
                 .schema(serde_json::json!({
                     "type": "object",
                     "properties": {
-                        "schema": { "type": "string", "default": "public", "description": "Schema name" }
+                        "schema": { "type": "string", "default": "public", "description": "Schema name" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
                     }
                 })),
             MethodInfo::new("postgres.schema", "Get table schema (columns, constraints, indexes)")
@@ -211,22 +851,40 @@ impl FgpService for PostgresService {
                     "type": "object",
                     "properties": {
                         "table": { "type": "string", "description": "Table name" },
-                        "schema": { "type": "string", "default": "public", "description": "Schema name" }
+                        "schema": { "type": "string", "default": "public", "description": "Schema name" },
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
                     },
                     "required": ["table"]
                 })),
-            MethodInfo::new("postgres.schemas", "List all schemas in the database"),
-            MethodInfo::new("postgres.stats", "Get database statistics (size, connections, table count)"),
+            MethodInfo::new("postgres.schemas", "List all schemas in the database")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    }
+                })),
+            MethodInfo::new("postgres.stats", "Get database statistics (size, connections, table count)")
+                .schema(serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "connection": { "type": "string", "description": "Named connection from connections.json (default: the daemon's)" }
+                    }
+                })),
+            MethodInfo::new("postgres.connections", "List the named connections this daemon serves (passwords masked)"),
         ]
     }
 
     fn on_start(&self) -> Result<()> {
+        let client = self
+            .runtime
+            .block_on(self.connections.get(None))?
+            .primary
+            .clone();
         tracing::info!(
             "PostgresService starting, connecting to {}...",
-            self.client.connection_info()
+            client.connection_info()
         );
 
-        let client = self.client.clone();
         self.runtime.block_on(async move {
             match client.ping().await {
                 Ok(true) => {
@@ -237,6 +895,14 @@ impl FgpService for PostgresService {
                     tracing::warn!("PostgreSQL ping returned false");
                     Ok(())
                 }
+                Err(e) if client.config().lazy => {
+                    tracing::warn!(
+                        "PostgreSQL not reachable yet, connecting in the background: {:#}",
+                        e
+                    );
+                    client.mark_unavailable(&e);
+                    Ok(())
+                }
                 Err(e) => {
                     tracing::error!("Failed to connect to PostgreSQL: {}", e);
                     Err(e)
@@ -248,7 +914,13 @@ impl FgpService for PostgresService {
     fn health_check(&self) -> HashMap<String, HealthStatus> {
         let mut checks = HashMap::new();
 
-        let client = self.client.clone();
+        let client = match self.runtime.block_on(self.connections.get(None)) {
+            Ok(servers) => servers.primary.clone(),
+            Err(e) => {
+                checks.insert("postgres".into(), HealthStatus::unhealthy(e.to_string()));
+                return checks;
+            }
+        };
         let start = std::time::Instant::now();
         let result = self.runtime.block_on(async move { client.ping().await });
 
@@ -0,0 +1,240 @@
+//! Interactive transactions spanning several requests on a pinned connection.
+
+use anyhow::{Context, Result};
+use deadpool_postgres::Object;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_postgres::IsolationLevel;
+
+use crate::client::TransactionOptions;
+use crate::copy::quote_ident;
+use crate::error;
+
+/// Sessions idle for this long are rolled back, unless `begin` asks otherwise.
+pub const DEFAULT_SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Longest idle timeout a session may ask for.
+pub const MAX_SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Upper bound on open sessions; it is lowered further to keep a pooled
+/// connection free for one-shot requests.
+pub const MAX_SESSIONS: usize = 8;
+
+/// An open transaction on its own pooled connection.
+pub struct Session {
+    /// `None` once the session has ended.
+    conn: Option<Object>,
+    idle_timeout: Duration,
+    last_used: Instant,
+    /// A statement failed; only a rollback (to a savepoint) can continue.
+    aborted: bool,
+}
+
+impl Session {
+    /// Start a transaction on `conn`.
+    pub async fn begin(
+        conn: Object,
+        options: &TransactionOptions,
+        idle_timeout: Duration,
+    ) -> Result<Self> {
+        conn.batch_execute(&begin_statement(options))
+            .await
+            .context("Failed to start transaction")?;
+        Ok(Self {
+            conn: Some(conn),
+            idle_timeout,
+            last_used: Instant::now(),
+            aborted: false,
+        })
+    }
+
+    /// The pinned connection, inside the session's transaction.
+    pub fn client(&mut self) -> Result<&Object> {
+        self.last_used = Instant::now();
+        self.conn
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Session has ended"))
+    }
+
+    /// Record the outcome of a statement run through `client`.
+    ///
+    /// Server errors abort the transaction, so the error says how to recover.
+    pub fn track<T>(&mut self, result: Result<T>) -> Result<T> {
+        self.last_used = Instant::now();
+        match result {
+            Err(e) if error::db_error(&e).is_some() => {
+                self.aborted = true;
+                Err(e)
+            }
+            result => result,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    pub async fn savepoint(&mut self, name: &str) -> Result<()> {
+        self.run(&format!("SAVEPOINT {}", quote_ident(name))).await
+    }
+
+    pub async fn release_savepoint(&mut self, name: &str) -> Result<()> {
+        self.run(&format!("RELEASE SAVEPOINT {}", quote_ident(name)))
+            .await
+    }
+
+    /// Undo everything since `name` was set; this also recovers an aborted transaction.
+    pub async fn rollback_to(&mut self, name: &str) -> Result<()> {
+        self.run(&format!("ROLLBACK TO SAVEPOINT {}", quote_ident(name)))
+            .await?;
+        self.aborted = false;
+        Ok(())
+    }
+
+    async fn run(&mut self, sql: &str) -> Result<()> {
+        let result = self.client()?.batch_execute(sql).await;
+        self.track(result.map_err(anyhow::Error::from))
+    }
+
+    /// Commit the transaction and hand the connection back to the pool.
+    ///
+    /// An aborted transaction is refused rather than silently rolled back, and
+    /// stays open so the caller can roll back to a savepoint.
+    pub async fn commit(&mut self) -> Result<()> {
+        if self.aborted {
+            anyhow::bail!(
+                "Transaction is aborted by an earlier error; roll back, or roll back to a savepoint"
+            );
+        }
+        self.end("COMMIT").await
+    }
+
+    /// Roll back the transaction and hand the connection back to the pool.
+    pub async fn rollback(&mut self) -> Result<()> {
+        self.end("ROLLBACK").await
+    }
+
+    /// The transaction is over either way; a connection that can't be cleanly
+    /// reset is discarded instead of returned.
+    async fn end(&mut self, sql: &str) -> Result<()> {
+        let conn = self
+            .conn
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Session has ended"))?;
+        match conn.batch_execute(sql).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.as_db_error().is_some() => Err(e.into()),
+            Err(e) => {
+                let _ = Object::take(conn);
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// `BEGIN` with the requested transaction modes.
+fn begin_statement(options: &TransactionOptions) -> String {
+    let mut modes = Vec::new();
+    if let Some(isolation) = options.isolation {
+        modes.push(match isolation {
+            IsolationLevel::ReadUncommitted => "ISOLATION LEVEL READ UNCOMMITTED",
+            IsolationLevel::RepeatableRead => "ISOLATION LEVEL REPEATABLE READ",
+            IsolationLevel::Serializable => "ISOLATION LEVEL SERIALIZABLE",
+            _ => "ISOLATION LEVEL READ COMMITTED",
+        });
+    }
+    match options.read_only {
+        Some(true) => modes.push("READ ONLY"),
+        Some(false) => modes.push("READ WRITE"),
+        None => {}
+    }
+    match options.deferrable {
+        Some(true) => modes.push("DEFERRABLE"),
+        Some(false) => modes.push("NOT DEFERRABLE"),
+        None => {}
+    }
+    if modes.is_empty() {
+        "BEGIN".to_string()
+    } else {
+        format!("BEGIN {}", modes.join(", "))
+    }
+}
+
+/// Open sessions by id.
+#[derive(Default)]
+pub struct Sessions {
+    open: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Session>>>>,
+}
+
+impl Sessions {
+    pub fn len(&self) -> usize {
+        self.open.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.open.lock().unwrap().is_empty()
+    }
+
+    /// Register a session and return its id, unless `limit` sessions are already open.
+    ///
+    /// The session is handed back when it can't be registered.
+    pub fn insert(&self, session: Session, limit: usize) -> Result<String, Box<Session>> {
+        let mut open = self.open.lock().unwrap();
+        if open.len() >= limit {
+            return Err(Box::new(session));
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        open.insert(id.clone(), Arc::new(tokio::sync::Mutex::new(session)));
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Arc<tokio::sync::Mutex<Session>>> {
+        self.open
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| unknown_session(id))
+    }
+
+    pub fn remove(&self, id: &str) -> Result<Arc<tokio::sync::Mutex<Session>>> {
+        self.open
+            .lock()
+            .unwrap()
+            .remove(id)
+            .ok_or_else(|| unknown_session(id))
+    }
+
+    /// Unregister and return sessions idle for longer than their timeout.
+    ///
+    /// Sessions in the middle of a request are never considered idle.
+    pub fn take_expired(&self) -> Vec<(String, Arc<tokio::sync::Mutex<Session>>)> {
+        let mut open = self.open.lock().unwrap();
+        let expired: Vec<String> = open
+            .iter()
+            .filter(|(_, session)| {
+                session
+                    .try_lock()
+                    .map(|s| s.last_used.elapsed() > s.idle_timeout)
+                    .unwrap_or(false)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| open.remove(&id).map(|session| (id, session)))
+            .collect()
+    }
+}
+
+fn unknown_session(id: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Unknown or expired session: {} (idle sessions are rolled back)",
+        id
+    )
+}
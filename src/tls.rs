@@ -0,0 +1,166 @@
+//! TLS setup following libpq's `sslmode` semantics.
+
+use anyhow::{Context, Result};
+use native_tls::{Certificate, Identity, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::client::ConnectionConfig;
+
+/// libpq `sslmode` values.
+///
+/// `allow` is treated like `prefer`: the driver tries TLS first and falls back
+/// to plaintext, rather than the other way round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    Disable,
+    Allow,
+    #[default]
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+impl SslMode {
+    /// The libpq spelling of this mode.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SslMode::Disable => "disable",
+            SslMode::Allow => "allow",
+            SslMode::Prefer => "prefer",
+            SslMode::Require => "require",
+            SslMode::VerifyCa => "verify-ca",
+            SslMode::VerifyFull => "verify-full",
+        }
+    }
+
+    /// Negotiation mode handed to the driver.
+    pub fn pool_mode(&self) -> deadpool_postgres::SslMode {
+        match self {
+            SslMode::Disable => deadpool_postgres::SslMode::Disable,
+            SslMode::Allow | SslMode::Prefer => deadpool_postgres::SslMode::Prefer,
+            SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => {
+                deadpool_postgres::SslMode::Require
+            }
+        }
+    }
+}
+
+impl FromStr for SslMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "disable" => Ok(SslMode::Disable),
+            "allow" => Ok(SslMode::Allow),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            _ => anyhow::bail!(
+                "Invalid sslmode '{}' (expected disable, allow, prefer, require, verify-ca or verify-full)",
+                s
+            ),
+        }
+    }
+}
+
+/// Build the TLS connector for a connection, or `None` when TLS is disabled.
+///
+/// Like libpq, `prefer` and `require` encrypt without verifying the server,
+/// unless `require` finds a root certificate, in which case it behaves like
+/// `verify-ca`. The verify modes need `sslrootcert` (a PEM file, or `system`
+/// for the platform trust store), defaulting to `~/.postgresql/root.crt`.
+pub fn make_connector(config: &ConnectionConfig) -> Result<Option<MakeTlsConnector>> {
+    let mode = config.sslmode;
+    if mode == SslMode::Disable {
+        return Ok(None);
+    }
+
+    let root_cert = config
+        .sslrootcert
+        .clone()
+        .or_else(|| default_file("root.crt"));
+    let verify_chain = match mode {
+        SslMode::VerifyCa | SslMode::VerifyFull => true,
+        SslMode::Require => root_cert.is_some(),
+        _ => false,
+    };
+
+    let mut builder = TlsConnector::builder();
+    if verify_chain {
+        match root_cert.as_deref() {
+            Some("system") => {}
+            Some(path) => {
+                let pem = read_file(path, "sslrootcert")?;
+                builder.disable_built_in_roots(true);
+                for cert in split_pem_certificates(&pem) {
+                    let cert = Certificate::from_pem(cert.as_bytes())
+                        .with_context(|| format!("Invalid certificate in sslrootcert {}", path))?;
+                    builder.add_root_certificate(cert);
+                }
+            }
+            None => anyhow::bail!(
+                "sslmode={} needs a root certificate: set sslrootcert to a PEM file, \
+                 or sslrootcert=system to use the system trust store",
+                mode.as_str()
+            ),
+        }
+        builder.danger_accept_invalid_hostnames(mode != SslMode::VerifyFull);
+    } else {
+        builder.danger_accept_invalid_certs(true);
+        builder.danger_accept_invalid_hostnames(true);
+    }
+
+    let client_cert = config
+        .sslcert
+        .clone()
+        .or_else(|| default_file("postgresql.crt"));
+    let client_key = config
+        .sslkey
+        .clone()
+        .or_else(|| default_file("postgresql.key"));
+    match (client_cert, client_key) {
+        (Some(cert), Some(key)) => {
+            let cert_pem = read_file(&cert, "sslcert")?;
+            let key_pem = read_file(&key, "sslkey")?;
+            let identity = Identity::from_pkcs8(cert_pem.as_bytes(), key_pem.as_bytes())
+                .context("Invalid sslcert/sslkey (the key must be an unencrypted PKCS#8 PEM)")?;
+            builder.identity(identity);
+        }
+        (Some(_), None) if config.sslcert.is_some() => {
+            anyhow::bail!("sslcert is set but no sslkey was given")
+        }
+        (None, Some(_)) if config.sslkey.is_some() => {
+            anyhow::bail!("sslkey is set but no sslcert was given")
+        }
+        _ => {}
+    }
+
+    let connector = builder.build().context("Failed to build TLS connector")?;
+    Ok(Some(MakeTlsConnector::new(connector)))
+}
+
+/// Default libpq certificate location under `~/.postgresql`, if present.
+fn default_file(name: &str) -> Option<String> {
+    let path: PathBuf = dirs::home_dir()?.join(".postgresql").join(name);
+    path.exists().then(|| path.to_string_lossy().into_owned())
+}
+
+fn read_file(path: &str, what: &str) -> Result<String> {
+    let expanded = shellexpand::tilde(path).to_string();
+    std::fs::read_to_string(&expanded).with_context(|| format!("Failed to read {} {}", what, path))
+}
+
+/// Split a PEM bundle into its individual certificates.
+fn split_pem_certificates(pem: &str) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
+    pem.split_inclusive(END)
+        .filter(|block| block.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|block| block.trim().to_string())
+        .collect()
+}
@@ -0,0 +1,218 @@
+//! Wire codecs for PostgreSQL types without a native Rust mapping.
+
+use bytes::{Buf, BufMut, BytesMut};
+use std::error::Error;
+use std::fmt;
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, Kind, ToSql, Type};
+
+const NUMERIC_POS: u16 = 0x0000;
+const NUMERIC_NEG: u16 = 0x4000;
+const NUMERIC_NAN: u16 = 0xC000;
+const NUMERIC_PINF: u16 = 0xD000;
+const NUMERIC_NINF: u16 = 0xF000;
+
+/// Largest number of decimal digits accepted when parsing a NUMERIC.
+const NUMERIC_MAX_DIGITS: usize = 131_072 + 16_383;
+
+/// Human-readable type name, e.g. `int4[]` instead of `_int4`.
+pub fn type_name(ty: &Type) -> String {
+    match ty.kind() {
+        Kind::Array(member) => format!("{}[]", type_name(member)),
+        _ => ty.name().to_string(),
+    }
+}
+
+/// Exact NUMERIC value in Postgres' base-10000 binary layout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Numeric {
+    sign: u16,
+    weight: i16,
+    dscale: u16,
+    digits: Vec<i16>,
+}
+
+impl Numeric {
+    /// Parse a decimal string such as `-12.340`, `1e-3`, `NaN` or `Infinity`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let special = |sign| Numeric {
+            sign,
+            weight: 0,
+            dscale: 0,
+            digits: Vec::new(),
+        };
+        match s.to_ascii_lowercase().as_str() {
+            "nan" => return Ok(special(NUMERIC_NAN)),
+            "infinity" | "+infinity" | "inf" | "+inf" => return Ok(special(NUMERIC_PINF)),
+            "-infinity" | "-inf" => return Ok(special(NUMERIC_NINF)),
+            _ => {}
+        }
+
+        let invalid = || format!("invalid numeric value {:?}", s);
+
+        let (negative, unsigned) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+            Some(pos) => {
+                let exp: i64 = unsigned[pos + 1..].parse().map_err(|_| invalid())?;
+                (&unsigned[..pos], exp)
+            }
+            None => (unsigned, 0),
+        };
+        let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if int_part.is_empty() && frac_part.is_empty()
+            || !int_part.bytes().all(|b| b.is_ascii_digit())
+            || !frac_part.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        // Decimal digits plus the position of the decimal point within them.
+        let mut decimal: Vec<u8> = int_part
+            .bytes()
+            .chain(frac_part.bytes())
+            .map(|b| b - b'0')
+            .collect();
+        let point = int_part.len() as i64 + exponent;
+        if exponent.unsigned_abs() as usize > NUMERIC_MAX_DIGITS
+            || decimal.len() > NUMERIC_MAX_DIGITS
+        {
+            return Err(format!("numeric value {:?} is out of range", s));
+        }
+        let dscale = (decimal.len() as i64 - point).max(0);
+        if point > decimal.len() as i64 {
+            decimal.resize(point as usize, 0);
+        }
+
+        // Align the decimal point on a base-10000 group boundary.
+        let lead = (4 - point.rem_euclid(4)) % 4;
+        let mut padded = vec![0u8; lead as usize];
+        padded.extend_from_slice(&decimal);
+        padded.resize(padded.len().div_ceil(4) * 4, 0);
+        let mut weight = (point + lead) / 4 - 1;
+        let mut digits: Vec<i16> = padded
+            .chunks(4)
+            .map(|c| c.iter().fold(0i16, |acc, &d| acc * 10 + d as i16))
+            .collect();
+
+        let leading_zeros = digits.iter().take_while(|&&d| d == 0).count();
+        digits.drain(..leading_zeros);
+        weight -= leading_zeros as i64;
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+
+        let sign = if negative && !digits.is_empty() {
+            NUMERIC_NEG
+        } else {
+            NUMERIC_POS
+        };
+        if digits.is_empty() {
+            weight = 0;
+        }
+
+        Ok(Numeric {
+            sign,
+            weight: i16::try_from(weight)
+                .map_err(|_| format!("numeric value {:?} is out of range", s))?,
+            dscale: u16::try_from(dscale)
+                .map_err(|_| format!("numeric value {:?} is out of range", s))?,
+            digits,
+        })
+    }
+}
+
+impl ToSql for Numeric {
+    fn to_sql(
+        &self,
+        _ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_i16(i16::try_from(self.digits.len())?);
+        out.put_i16(self.weight);
+        out.put_u16(self.sign);
+        out.put_u16(self.dscale);
+        for digit in &self.digits {
+            out.put_i16(*digit);
+        }
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::NUMERIC
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Numeric {
+    fn from_sql(_ty: &Type, mut raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        if raw.len() < 8 {
+            return Err("invalid numeric buffer size".into());
+        }
+        let ndigits = raw.get_i16();
+        let weight = raw.get_i16();
+        let sign = raw.get_u16();
+        let dscale = raw.get_u16();
+        if ndigits < 0 || raw.len() != ndigits as usize * 2 {
+            return Err("invalid numeric digit count".into());
+        }
+        let digits = (0..ndigits).map(|_| raw.get_i16()).collect();
+        Ok(Numeric {
+            sign,
+            weight,
+            dscale,
+            digits,
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::NUMERIC
+    }
+}
+
+impl fmt::Display for Numeric {
+    /// Formats exactly as Postgres' text output does, keeping the display scale.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.sign {
+            NUMERIC_NAN => return f.write_str("NaN"),
+            NUMERIC_PINF => return f.write_str("Infinity"),
+            NUMERIC_NINF => return f.write_str("-Infinity"),
+            NUMERIC_NEG => f.write_str("-")?,
+            _ => {}
+        }
+
+        let digit = |i: i64| {
+            usize::try_from(i)
+                .ok()
+                .and_then(|i| self.digits.get(i))
+                .copied()
+                .unwrap_or(0)
+        };
+
+        if self.weight < 0 {
+            f.write_str("0")?;
+        } else {
+            write!(f, "{}", digit(0))?;
+            for i in 1..=self.weight as i64 {
+                write!(f, "{:04}", digit(i))?;
+            }
+        }
+
+        if self.dscale > 0 {
+            let scale = self.dscale as usize;
+            let mut fraction = String::with_capacity(scale + 4);
+            let mut i = self.weight as i64 + 1;
+            while fraction.len() < scale {
+                fraction.push_str(&format!("{:04}", digit(i)));
+                i += 1;
+            }
+            fraction.truncate(scale);
+            write!(f, ".{}", fraction)?;
+        }
+        Ok(())
+    }
+}